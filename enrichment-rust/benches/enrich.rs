@@ -0,0 +1,51 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use enrichment_rust_lib::{
+    airlines, currency_exchange, emissions, enrich_json_str, enrich_json_with_options, neobase,
+    EnrichOptions,
+};
+
+const NEOBASE_SAMPLE: &str = include_str!("../src/fixtures/neobase_sample.csv");
+const SAMPLE_SEARCH: &str = include_str!("../src/fixtures/sample_search.json");
+
+/// Enriches the sample search from and to text, through `serde_json::Value` and without.
+fn enrich(c: &mut Criterion) {
+    let neobase_locations = neobase::Locations::from_reader(NEOBASE_SAMPLE.as_bytes());
+    let exchange_rates = currency_exchange::ExchangeRates::new();
+    let emissions_model = emissions::EmissionsModel::new();
+    let airlines = airlines::Airlines::new();
+
+    let mut group = c.benchmark_group("enrich_sample_search");
+    group.bench_function("enrich_json", |b| {
+        b.iter(|| {
+            let input_json = serde_json::from_str(black_box(SAMPLE_SEARCH)).unwrap();
+            let output_json = enrich_json_with_options(
+                input_json,
+                &neobase_locations,
+                &exchange_rates,
+                &emissions_model,
+                &airlines,
+                EnrichOptions::default(),
+            )
+            .unwrap();
+            serde_json::to_string(&output_json).unwrap()
+        })
+    });
+    group.bench_function("enrich_json_str", |b| {
+        b.iter(|| {
+            enrich_json_str(
+                black_box(SAMPLE_SEARCH),
+                &neobase_locations,
+                &exchange_rates,
+                &emissions_model,
+                &airlines,
+                EnrichOptions::default(),
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, enrich);
+criterion_main!(benches);
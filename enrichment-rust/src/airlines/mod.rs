@@ -0,0 +1,188 @@
+use std::{collections::HashMap, io::Read};
+
+use serde::Serialize;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AirlinesError {
+    #[error("Failed to read airline: {0:?}")]
+    Airline(#[source] csv::Error),
+    #[error("Failed to read low-cost carrier: {0:?}")]
+    LowCostCarrier(#[source] csv::Error),
+}
+
+/// A line of an OPTD-formatted (`^`-separated) airline file. Other OPTD columns are ignored.
+#[derive(serde::Deserialize)]
+struct Record {
+    #[serde(rename = "2char_code")]
+    iata_code: String,
+    #[serde(rename = "3char_code", default)]
+    icao_code: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    validity_to: String,
+    #[serde(default)]
+    alliance_code: String,
+    #[serde(default)]
+    alliance_status: String,
+}
+
+#[derive(serde::Deserialize)]
+struct LowCostRecord {
+    #[serde(rename = "2char_code")]
+    iata_code: String,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Alliance {
+    #[serde(rename = "Star Alliance")]
+    StarAlliance,
+    #[serde(rename = "oneworld")]
+    Oneworld,
+    #[serde(rename = "SkyTeam")]
+    SkyTeam,
+}
+
+impl Alliance {
+    fn from_optd(alliance_code: &str) -> Option<Self> {
+        match alliance_code.to_lowercase().as_str() {
+            "star alliance" => Some(Alliance::StarAlliance),
+            "oneworld" => Some(Alliance::Oneworld),
+            "skyteam" => Some(Alliance::SkyTeam),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Airline {
+    pub iata_code: String,
+    pub icao_code: Option<String>,
+    pub name: String,
+    pub alliance: Option<Alliance>,
+    pub is_lcc: bool,
+}
+
+pub struct Airlines {
+    airlines: HashMap<String, Airline>,
+}
+
+impl Airlines {
+    /// Uses `data.csv` and `low_cost_carriers.csv`, embedded in the crate.
+    pub fn new() -> Self {
+        Self::from_readers(
+            include_str!("data.csv").as_bytes(),
+            include_str!("low_cost_carriers.csv").as_bytes(),
+        )
+        .expect("The embedded airline files are valid")
+    }
+
+    /// Loads an OPTD airline file (`^`-separated), and a `,`-separated list of low-cost carriers
+    /// with a `2char_code` column, as OPTD does not flag them.
+    pub fn from_readers<A: Read, L: Read>(
+        airlines_reader: A,
+        low_cost_reader: L,
+    ) -> Result<Self, AirlinesError> {
+        let low_cost_codes: Vec<String> = csv::Reader::from_reader(low_cost_reader)
+            .deserialize()
+            .map(|record| record.map(|record: LowCostRecord| record.iata_code))
+            .collect::<Result<_, _>>()
+            .map_err(AirlinesError::LowCostCarrier)?;
+
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .delimiter(b'^')
+            .from_reader(airlines_reader);
+
+        let mut airlines = HashMap::new();
+        for result in csv_reader.deserialize() {
+            let record: Record = result.map_err(AirlinesError::Airline)?;
+            if record.iata_code.is_empty() {
+                continue;
+            }
+            // OPTD keeps airlines that stopped flying: an active airline always wins over them
+            let is_active = record.validity_to.is_empty();
+            if !is_active && airlines.contains_key(&record.iata_code) {
+                continue;
+            }
+
+            // "Associate" members do not share the alliance benefits
+            let alliance = match record.alliance_status.as_str() {
+                "Associate" => None,
+                _ => Alliance::from_optd(&record.alliance_code),
+            };
+            let airline = Airline {
+                is_lcc: low_cost_codes.contains(&record.iata_code),
+                iata_code: record.iata_code.clone(),
+                icao_code: (!record.icao_code.is_empty()).then_some(record.icao_code),
+                name: record.name,
+                alliance,
+            };
+            airlines.insert(record.iata_code, airline);
+        }
+
+        Ok(Airlines { airlines })
+    }
+
+    pub fn get(&self, code: &str) -> Option<&Airline> {
+        self.airlines.get(code)
+    }
+
+    /// A valid code is a known two-character IATA airline designator.
+    pub fn is_valid_code(&self, code: &str) -> bool {
+        code.len() == 2
+            && code
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+            && self.airlines.contains_key(code)
+    }
+
+    pub fn get_alliance(&self, code: &str) -> Option<Alliance> {
+        self.get(code).and_then(|airline| airline.alliance)
+    }
+
+    pub fn is_lcc(&self, code: &str) -> bool {
+        self.get(code).is_some_and(|airline| airline.is_lcc)
+    }
+}
+
+impl Default for Airlines {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_airlines() {
+        let airlines = Airlines::new();
+
+        assert!(airlines.is_valid_code("KL"));
+        assert!(!airlines.is_valid_code("kl"));
+        assert!(!airlines.is_valid_code("ZZ"));
+        assert_eq!(
+            airlines.get("KL").unwrap().icao_code.as_deref(),
+            Some("KLM")
+        );
+        assert_eq!(airlines.get_alliance("KL"), Some(Alliance::SkyTeam));
+        assert_eq!(airlines.get_alliance("TP"), Some(Alliance::StarAlliance));
+        assert_eq!(airlines.get_alliance("U2"), None);
+        assert!(airlines.is_lcc("U2"));
+        assert!(!airlines.is_lcc("AF"));
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let airlines = "2char_code^name\nKL^KLM Royal Dutch Airlines\n";
+        assert!(matches!(
+            Airlines::from_readers(airlines.as_bytes(), "iata\nU2\n".as_bytes()),
+            Err(AirlinesError::LowCostCarrier(_))
+        ));
+        assert!(matches!(
+            Airlines::from_readers("name\nKLM\n".as_bytes(), "2char_code\nU2\n".as_bytes()),
+            Err(AirlinesError::Airline(_))
+        ));
+    }
+}
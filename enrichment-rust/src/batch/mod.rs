@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use rayon::ThreadPoolBuilder;
+
+use crate::{
+    enrich_json_with_options, reference_data::ReferenceData, EnrichJsonError, EnrichOptions,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum BatchError {
+    #[error("Failed to start the enrichment threads: {0:?}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+}
+
+/// Options of `enrich_batch`.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchOptions {
+    pub enrich_options: EnrichOptions,
+    /// Number of threads, one per CPU when `None`.
+    pub threads: Option<usize>,
+    /// Outputs the searches in the order of the input, instead of as soon as they are enriched.
+    pub preserve_order: bool,
+    /// Searches read ahead of the output, which bounds the memory used.
+    pub max_in_flight: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            enrich_options: EnrichOptions::default(),
+            threads: None,
+            preserve_order: true,
+            max_in_flight: 1024,
+        }
+    }
+}
+
+/// Throughput of a batch.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BatchReport {
+    pub nb_of_searches: u64,
+    pub nb_of_failed_searches: u64,
+    pub elapsed: Duration,
+}
+
+impl BatchReport {
+    pub fn searches_per_second(&self) -> f64 {
+        self.nb_of_searches as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    fn record<T>(&mut self, result: &Result<T, EnrichJsonError>) {
+        self.nb_of_searches += 1;
+        if result.is_err() {
+            self.nb_of_failed_searches += 1;
+        }
+    }
+}
+
+impl fmt::Display for BatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Enriched {} searches ({} failed) in {:.1}s, {:.0} searches/s",
+            self.nb_of_searches,
+            self.nb_of_failed_searches,
+            self.elapsed.as_secs_f64(),
+            self.searches_per_second()
+        )
+    }
+}
+
+/// Enriches searches into JSON like `enrich_json_with_options`, in parallel, and passes them to
+/// `output` on the calling thread.
+pub fn enrich_batch(
+    searches: impl IntoIterator<Item = serde_json::Value>,
+    reference_data: &ReferenceData,
+    options: BatchOptions,
+    output: impl FnMut(Result<serde_json::Value, EnrichJsonError>),
+) -> Result<BatchReport, BatchError> {
+    enrich_batch_with(
+        searches,
+        |search| {
+            enrich_json_with_options(
+                search,
+                &reference_data.locations,
+                &reference_data.exchange_rates,
+                &reference_data.emissions_model,
+                &reference_data.airlines,
+                options.enrich_options,
+            )
+        },
+        options,
+        output,
+    )
+}
+
+/// Same as `enrich_batch`, with any enrichment function, like `enrich_json_to_rows`.
+/// `options.enrich_options` is left to `enrich`.
+///
+/// The searches are read on the calling thread, so the input does not need to be `Send`.
+pub fn enrich_batch_with<T: Send>(
+    searches: impl IntoIterator<Item = serde_json::Value>,
+    enrich: impl Fn(serde_json::Value) -> Result<T, EnrichJsonError> + Sync,
+    options: BatchOptions,
+    mut output: impl FnMut(Result<T, EnrichJsonError>),
+) -> Result<BatchReport, BatchError> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(options.threads.unwrap_or(0))
+        .build()?;
+    let start = Instant::now();
+    let mut report = BatchReport::default();
+    let enrich = &enrich;
+
+    pool.in_place_scope(|scope| {
+        let (sender, receiver) = mpsc::channel::<(u64, std::thread::Result<_>)>();
+        // enriched searches waiting for the previous ones, when preserving the order
+        let mut pending = BTreeMap::new();
+        let mut receive = |report: &mut BatchReport| {
+            let (index, result) = receiver.recv().unwrap();
+            let result = result.unwrap_or_else(|payload| panic::resume_unwind(payload));
+            if !options.preserve_order {
+                report.record(&result);
+                output(result);
+                return;
+            }
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&report.nb_of_searches) {
+                report.record(&result);
+                output(result);
+            }
+        };
+
+        let mut nb_of_searches_read = 0;
+        for search in searches {
+            while nb_of_searches_read - report.nb_of_searches >= options.max_in_flight.max(1) as u64
+            {
+                receive(&mut report);
+            }
+            let sender = sender.clone();
+            let index = nb_of_searches_read;
+            scope.spawn(move |_| {
+                // a panic is raised again on the calling thread, instead of never being received
+                let result = panic::catch_unwind(AssertUnwindSafe(|| enrich(search)));
+                // the receiver is gone only when the calling thread panicked
+                let _ = sender.send((index, result));
+            });
+            nb_of_searches_read += 1;
+        }
+        while report.nb_of_searches < nb_of_searches_read {
+            receive(&mut report);
+        }
+    });
+
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{reference_data, sample_search};
+
+    fn parse(search: serde_json::Value) -> Result<u64, EnrichJsonError> {
+        let index = search["index"].as_u64().unwrap();
+        // later searches are faster, so that they complete out of order
+        std::thread::sleep(Duration::from_millis(20 - index));
+        serde_json::from_value(search["value"].clone())
+            .map_err(EnrichJsonError::FailedToParseSearch)
+    }
+
+    fn searches() -> Vec<serde_json::Value> {
+        (0..20)
+            .map(|index| match index {
+                7 => serde_json::json!({"index": index, "value": "invalid"}),
+                _ => serde_json::json!({"index": index, "value": index * 10}),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_enrich_batch_with() {
+        let options = BatchOptions {
+            threads: Some(4),
+            max_in_flight: 8,
+            ..BatchOptions::default()
+        };
+        let mut outputs = vec![];
+        let report = enrich_batch_with(searches(), parse, options, |result| {
+            outputs.push(result.ok())
+        })
+        .unwrap();
+        let expected: Vec<_> = (0..20)
+            .map(|index| (index != 7).then_some(index * 10))
+            .collect();
+        assert_eq!(outputs, expected);
+        assert_eq!(report.nb_of_searches, 20);
+        assert_eq!(report.nb_of_failed_searches, 1);
+        assert!(report.searches_per_second() > 0.0);
+
+        let mut outputs = vec![];
+        let options = BatchOptions {
+            preserve_order: false,
+            ..options
+        };
+        enrich_batch_with(searches(), parse, options, |result| {
+            outputs.push(result.ok())
+        })
+        .unwrap();
+        outputs.sort();
+        let mut expected = expected;
+        expected.sort();
+        assert_eq!(outputs, expected);
+    }
+
+    #[test]
+    fn test_enrich_batch() {
+        let reference_data = reference_data();
+        let mut invalid = sample_search();
+        invalid["origin_city"] = "XXX".into();
+        let searches = vec![sample_search(), invalid, sample_search()];
+
+        let mut outputs = vec![];
+        let report = enrich_batch(
+            searches,
+            &reference_data,
+            BatchOptions {
+                threads: Some(2),
+                ..BatchOptions::default()
+            },
+            |result| outputs.push(result.ok()),
+        )
+        .unwrap();
+        let expected = enrich_json_with_options(
+            sample_search(),
+            &reference_data.locations,
+            &reference_data.exchange_rates,
+            &reference_data.emissions_model,
+            &reference_data.airlines,
+            EnrichOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(outputs, vec![Some(expected.clone()), None, Some(expected)]);
+        assert_eq!(report.nb_of_searches, 3);
+        assert_eq!(report.nb_of_failed_searches, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "enrichment bug")]
+    fn test_enrich_batch_with_panic() {
+        let options = BatchOptions {
+            threads: Some(2),
+            ..BatchOptions::default()
+        };
+        let _ = enrich_batch_with(
+            searches(),
+            |_| -> Result<(), EnrichJsonError> { panic!("enrichment bug") },
+            options,
+            |_| {},
+        );
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::{
+    flatten::{FlatSearch, RecoRow},
+    serde_json_helpers::serialize_f64_2_decimals,
+    sketch::TDigest,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum BenchmarkError {
+    #[error("Cannot merge a benchmark by {found:?} into one by {expected:?}")]
+    DimensionMismatch {
+        expected: Vec<Dimension>,
+        found: Vec<Dimension>,
+    },
+}
+
+/// What price benchmarks can be grouped by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dimension {
+    Ond,
+    TripType,
+    /// Main marketing airline of the recos.
+    Airline,
+    /// Advance purchase in buckets of `bucket_days` days: 7 gives "0-6", "7-13"...
+    AdvancePurchase {
+        bucket_days: u64,
+    },
+    /// Normalized main cabin of the recos (M, W, C, F), empty when unknown.
+    Cabin,
+    /// Stay duration in days, -1 for one ways.
+    StayDuration,
+}
+
+impl Dimension {
+    /// Name of the dimension in the JSON export, the same as the enriched field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dimension::Ond => "OnD",
+            Dimension::TripType => "trip_type",
+            Dimension::Airline => "airline",
+            Dimension::AdvancePurchase { .. } => "advance_purchase",
+            Dimension::Cabin => "cabin",
+            Dimension::StayDuration => "stay_duration",
+        }
+    }
+
+    fn value(&self, flat_search: &FlatSearch, reco: &RecoRow) -> DimensionValue {
+        let search = &flat_search.search;
+        match self {
+            Dimension::Ond => DimensionValue::Text(search.ond.clone()),
+            Dimension::TripType => DimensionValue::Text(search.trip_type.clone()),
+            Dimension::Airline => DimensionValue::Text(reco.main_marketing_airline.clone()),
+            Dimension::AdvancePurchase { bucket_days } if *bucket_days <= 1 => {
+                DimensionValue::Number(search.advance_purchase as i64)
+            }
+            Dimension::AdvancePurchase { bucket_days } => {
+                let start = search.advance_purchase / bucket_days * bucket_days;
+                DimensionValue::Range(start, start + bucket_days - 1)
+            }
+            Dimension::Cabin => {
+                DimensionValue::Text(reco.main_cabin_class.clone().unwrap_or_default())
+            }
+            Dimension::StayDuration => {
+                DimensionValue::Number(search.stay_duration.map_or(-1, |days| days as i64))
+            }
+        }
+    }
+}
+
+/// Value of a dimension for a group, so that groups sort numerically by days rather than as
+/// text ("7-13" before "14-20").
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum DimensionValue {
+    Number(i64),
+    /// First and last day of a bucket.
+    Range(u64, u64),
+    Text(String),
+}
+
+impl fmt::Display for DimensionValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DimensionValue::Number(number) => write!(f, "{number}"),
+            DimensionValue::Range(start, end) => write!(f, "{start}-{end}"),
+            DimensionValue::Text(text) => f.write_str(text),
+        }
+    }
+}
+
+/// Searches to benchmark. Missing bounds are open.
+#[derive(Clone, Debug, Default)]
+pub struct BenchmarkFilter {
+    pub ond: Option<String>,
+    pub trip_type: Option<String>,
+    pub search_date_min: Option<NaiveDate>,
+    pub search_date_max: Option<NaiveDate>,
+}
+
+impl BenchmarkFilter {
+    fn matches(&self, flat_search: &FlatSearch) -> bool {
+        let search = &flat_search.search;
+        self.ond.as_ref().is_none_or(|ond| *ond == search.ond)
+            && self
+                .trip_type
+                .as_ref()
+                .is_none_or(|trip_type| *trip_type == search.trip_type)
+            && self
+                .search_date_min
+                .is_none_or(|min| min <= search.search_date)
+            && self
+                .search_date_max
+                .is_none_or(|max| search.search_date <= max)
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct QuantilePrice {
+    pub quantile: f64,
+    #[serde(rename = "price_EUR", serialize_with = "serialize_f64_2_decimals")]
+    pub price_eur: f64,
+}
+
+/// Price statistics of a group, over the cheapest price of each search in the group.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct BenchmarkGroup {
+    /// Value of each dimension, by name.
+    #[serde(flatten)]
+    pub dimensions: BTreeMap<&'static str, String>,
+    pub nb_of_searches: u64,
+    #[serde(rename = "min_price_EUR", serialize_with = "serialize_f64_2_decimals")]
+    pub min_price_eur: f64,
+    #[serde(rename = "mean_price_EUR", serialize_with = "serialize_f64_2_decimals")]
+    pub mean_price_eur: f64,
+    pub quantiles: Vec<QuantilePrice>,
+}
+
+/// Price distribution of a group, to be merged with the sketches of other partitions or windows.
+#[derive(Serialize, Clone, Debug)]
+pub struct PriceSketch {
+    #[serde(flatten)]
+    pub dimensions: BTreeMap<&'static str, String>,
+    #[serde(rename = "price_EUR")]
+    pub sketch: TDigest,
+}
+
+/// Aggregates the prices of enriched searches into benchmarks, like `backend/server.py` does with
+/// Druid: each search contributes its cheapest price to each group it has recos in, then the
+/// groups get the minimum, the mean and the quantiles of these prices. Quantiles are estimated
+/// with a t-digest per group, so memory does not grow with the number of searches.
+///
+/// `backend/server.py` groups by `[Airline, AdvancePurchase { bucket_days: 1 }]` for the median
+/// (quantile 0.5), filtered on an OnD, a trip type and a search date range.
+pub struct PriceBenchmark {
+    dimensions: Vec<Dimension>,
+    quantiles: Vec<f64>,
+    filter: BenchmarkFilter,
+    groups: BTreeMap<Vec<DimensionValue>, TDigest>,
+}
+
+impl PriceBenchmark {
+    pub fn new(dimensions: Vec<Dimension>, quantiles: Vec<f64>) -> Self {
+        PriceBenchmark {
+            dimensions,
+            quantiles,
+            filter: BenchmarkFilter::default(),
+            groups: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_filter(mut self, filter: BenchmarkFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn add(&mut self, flat_search: &FlatSearch) {
+        if !self.filter.matches(flat_search) {
+            return;
+        }
+        let mut cheapest: HashMap<Vec<DimensionValue>, f64> = HashMap::new();
+        for reco in &flat_search.recos {
+            let key = self
+                .dimensions
+                .iter()
+                .map(|dimension| dimension.value(flat_search, reco))
+                .collect();
+            cheapest
+                .entry(key)
+                .and_modify(|price| *price = price.min(reco.price_eur))
+                .or_insert(reco.price_eur);
+        }
+        for (key, price) in cheapest {
+            self.groups.entry(key).or_default().add(price);
+        }
+    }
+
+    /// Adds the searches of a benchmark with the same dimensions, built on another partition.
+    pub fn merge(&mut self, other: &PriceBenchmark) -> Result<(), BenchmarkError> {
+        if self.dimensions != other.dimensions {
+            return Err(BenchmarkError::DimensionMismatch {
+                expected: self.dimensions.clone(),
+                found: other.dimensions.clone(),
+            });
+        }
+        for (key, sketch) in &other.groups {
+            self.groups.entry(key.clone()).or_default().merge(sketch);
+        }
+        Ok(())
+    }
+
+    fn dimensions(&self, key: &[DimensionValue]) -> BTreeMap<&'static str, String> {
+        self.dimensions
+            .iter()
+            .map(Dimension::name)
+            .zip(key.iter().map(DimensionValue::to_string))
+            .collect()
+    }
+
+    /// The price distribution of each group, sorted by dimension values.
+    pub fn sketches(&self) -> Vec<PriceSketch> {
+        self.groups
+            .iter()
+            .map(|(key, sketch)| PriceSketch {
+                dimensions: self.dimensions(key),
+                sketch: sketch.clone(),
+            })
+            .collect()
+    }
+
+    /// The groups, sorted by dimension values.
+    pub fn results(&self) -> Vec<BenchmarkGroup> {
+        self.groups
+            .iter()
+            // groups are created with a price
+            .map(|(key, sketch)| BenchmarkGroup {
+                dimensions: self.dimensions(key),
+                nb_of_searches: sketch.count(),
+                min_price_eur: sketch.min().unwrap(),
+                mean_price_eur: sketch.mean().unwrap(),
+                quantiles: self
+                    .quantiles
+                    .iter()
+                    .map(|q| QuantilePrice {
+                        quantile: *q,
+                        price_eur: sketch.quantile(*q).unwrap(),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        // only strings and numbers
+        serde_json::to_value(self.results()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatten::SearchRow;
+
+    /// A search of `ond`, `advance_purchase` days before the departure, with a reco per airline,
+    /// normalized cabin and price.
+    fn search(ond: &str, advance_purchase: u64, recos: &[(&str, &str, f64)]) -> FlatSearch {
+        let search_date = NaiveDate::from_ymd_opt(2021, 11, 17).unwrap();
+        let (origin_city, destination_city) = ond.split_once('-').unwrap();
+        FlatSearch {
+            search: SearchRow {
+                search_id: format!("{ond}-{advance_purchase}"),
+                search_country: "FR".to_string(),
+                search_date,
+                request_dep_date: search_date + chrono::Days::new(advance_purchase),
+                request_return_date: None,
+                advance_purchase,
+                stay_duration: None,
+                trip_type: "OW".to_string(),
+                ond: ond.to_string(),
+                origin_city: origin_city.to_string(),
+                destination_city: destination_city.to_string(),
+                origin_country: String::new(),
+                destination_country: String::new(),
+                geo: None,
+                ond_distance: 0,
+                currency: "EUR".to_string(),
+                nb_of_recos: recos.len() as u64,
+                min_price_eur: None,
+                max_price_eur: None,
+                median_price_eur: None,
+                cheapest_direct_price_eur: None,
+                nb_of_airlines: 0,
+            },
+            recos: recos
+                .iter()
+                .enumerate()
+                .map(|(reco_index, (airline, cabin, price))| RecoRow {
+                    search_id: format!("{ond}-{advance_purchase}"),
+                    reco_index: reco_index as u64,
+                    nb_of_flights: 1,
+                    price_eur: *price,
+                    taxes_eur: 0.0,
+                    fees_eur: 0.0,
+                    main_marketing_airline: airline.to_string(),
+                    main_operating_airline: airline.to_string(),
+                    main_airline_alliance: None,
+                    main_cabin: cabin.to_string(),
+                    main_cabin_class: Some(cabin.to_string()),
+                    trip_type: "OW".to_string(),
+                    flown_distance: 0,
+                    co2_kg: 0.0,
+                    circuity: 1.0,
+                    is_direct: true,
+                    is_lcc: false,
+                    is_codeshare: false,
+                    is_interline: false,
+                    is_duplicate: false,
+                    price_rank: 0,
+                    itinerary_fingerprint: String::new(),
+                })
+                .collect(),
+            flights: vec![],
+        }
+    }
+
+    fn by_airline_and_advance_purchase() -> PriceBenchmark {
+        // as in backend/server.py
+        PriceBenchmark::new(
+            vec![
+                Dimension::Airline,
+                Dimension::AdvancePurchase { bucket_days: 1 },
+            ],
+            vec![0.5],
+        )
+    }
+
+    #[test]
+    fn test_cheapest_price_per_search_and_group() {
+        let mut benchmark = by_airline_and_advance_purchase();
+        for price in [200.0, 150.0, 100.0] {
+            benchmark.add(&search(
+                "PAR-LIS",
+                30,
+                &[("KL", "M", 580.0), ("TP", "M", 250.0), ("TP", "M", price)],
+            ));
+        }
+
+        let json = benchmark.to_json();
+        assert_eq!(json[0]["airline"], "KL");
+        assert_eq!(json[0]["advance_purchase"], "30");
+        assert_eq!(json[0]["nb_of_searches"], 3);
+        assert_eq!(json[1]["airline"], "TP");
+        assert_eq!(json[1]["nb_of_searches"], 3);
+        assert_eq!(json[1]["min_price_EUR"], 100.0);
+        assert_eq!(json[1]["mean_price_EUR"], 150.0);
+        assert_eq!(json[1]["quantiles"][0]["quantile"], 0.5);
+        assert_eq!(json[1]["quantiles"][0]["price_EUR"], 150.0);
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut benchmark = by_airline_and_advance_purchase().with_filter(BenchmarkFilter {
+            ond: Some("PAR-LIS".to_string()),
+            trip_type: Some("OW".to_string()),
+            search_date_min: NaiveDate::from_ymd_opt(2021, 11, 1),
+            search_date_max: NaiveDate::from_ymd_opt(2021, 11, 30),
+        });
+        benchmark.add(&search("PAR-LIS", 30, &[("TP", "M", 200.0)]));
+        benchmark.add(&search("PAR-OPO", 30, &[("TP", "M", 100.0)]));
+        let mut round_trip = search("PAR-LIS", 30, &[("TP", "M", 100.0)]);
+        round_trip.search.trip_type = "RT".to_string();
+        benchmark.add(&round_trip);
+        let mut searched_later = search("PAR-LIS", 30, &[("TP", "M", 100.0)]);
+        searched_later.search.search_date = NaiveDate::from_ymd_opt(2021, 12, 1).unwrap();
+        benchmark.add(&searched_later);
+
+        let results = benchmark.results();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].nb_of_searches, 1);
+        assert_eq!(results[0].min_price_eur, 200.0);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut benchmark = by_airline_and_advance_purchase();
+        benchmark.add(&search("PAR-LIS", 30, &[("TP", "M", 200.0)]));
+        let mut partition = by_airline_and_advance_purchase();
+        partition.add(&search("PAR-LIS", 30, &[("TP", "M", 100.0)]));
+        partition.add(&search("PAR-LIS", 9, &[("TP", "M", 300.0)]));
+
+        benchmark.merge(&partition).unwrap();
+        let results = benchmark.results();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].dimensions["advance_purchase"], "30");
+        assert_eq!(results[1].nb_of_searches, 2);
+        assert_eq!(results[1].min_price_eur, 100.0);
+        let sketches = serde_json::to_value(benchmark.sketches()).unwrap();
+        assert_eq!(sketches[1]["airline"], "TP");
+        assert_eq!(sketches[1]["price_EUR"]["count"], 2.0);
+
+        let by_airline = PriceBenchmark::new(vec![Dimension::Airline], vec![0.5]);
+        assert!(matches!(
+            benchmark.merge(&by_airline),
+            Err(BenchmarkError::DimensionMismatch { .. })
+        ));
+        assert_eq!(benchmark.results(), results);
+    }
+
+    #[test]
+    fn test_buckets_sort_by_days() {
+        let searches = [
+            search("PAR-LIS", 30, &[("TP", "M", 200.0), ("TP", "C", 900.0)]),
+            search("PAR-LIS", 9, &[("TP", "M", 250.0)]),
+            search("PAR-LIS", 100, &[("TP", "M", 150.0)]),
+        ];
+        let group_values = |dimensions: Vec<Dimension>| {
+            let mut benchmark = PriceBenchmark::new(dimensions, vec![]);
+            for flat_search in &searches {
+                benchmark.add(flat_search);
+            }
+            benchmark
+                .results()
+                .into_iter()
+                .map(|group| group.dimensions.into_values().collect::<Vec<_>>().join(" "))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            group_values(vec![Dimension::AdvancePurchase { bucket_days: 1 }]),
+            vec!["9", "30", "100"]
+        );
+        // dimension values are joined in the order of their names
+        assert_eq!(
+            group_values(vec![
+                Dimension::AdvancePurchase { bucket_days: 7 },
+                Dimension::Cabin,
+            ]),
+            vec!["7-13 M", "28-34 C", "28-34 M", "98-104 M"]
+        );
+        assert_eq!(group_values(vec![Dimension::StayDuration]), vec!["-1"]);
+    }
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+/// Cabin class, ordered by rank: Economy < PremiumEconomy < Business < First.
+/// Serialized as the cabin codes used in the searches.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Cabin {
+    #[serde(rename = "M")]
+    Economy,
+    #[serde(rename = "W")]
+    PremiumEconomy,
+    #[serde(rename = "C")]
+    Business,
+    #[serde(rename = "F")]
+    First,
+}
+
+impl Cabin {
+    /// Maps a cabin code (M, W, C, F) or a booking class letter (RBD) to its cabin.
+    /// RBDs are airline specific: this is the most common mapping.
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code.trim().to_ascii_uppercase().as_str() {
+            "F" | "A" | "P" | "R" => Some(Cabin::First),
+            "C" | "J" | "D" | "I" | "Z" => Some(Cabin::Business),
+            "W" => Some(Cabin::PremiumEconomy),
+            "M" | "Y" | "B" | "H" | "K" | "L" | "V" | "S" | "N" | "Q" | "O" | "G" | "X" | "T"
+            | "E" | "U" => Some(Cabin::Economy),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cabin_mapping_and_rank() {
+        assert_eq!(Cabin::from_code("M"), Some(Cabin::Economy));
+        assert_eq!(Cabin::from_code("y"), Some(Cabin::Economy));
+        assert_eq!(Cabin::from_code("J"), Some(Cabin::Business));
+        assert_eq!(Cabin::from_code("W"), Some(Cabin::PremiumEconomy));
+        assert_eq!(Cabin::from_code("?"), None);
+        assert_eq!(Cabin::from_code("MM"), None);
+
+        assert!(Cabin::Economy < Cabin::PremiumEconomy);
+        assert!(Cabin::PremiumEconomy < Cabin::Business);
+        assert!(Cabin::Business < Cabin::First);
+    }
+}
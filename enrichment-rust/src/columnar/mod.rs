@@ -0,0 +1,492 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use arrow_array::{
+    builder::{
+        BooleanBuilder, Date32Builder, Decimal128Builder, Float64Builder, Int64Builder,
+        StringBuilder, StringDictionaryBuilder, TimestampSecondBuilder,
+    },
+    types::Int32Type,
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{NaiveDate, Utc};
+use parquet::{
+    arrow::ArrowWriter, basic::Compression, errors::ParquetError,
+    file::properties::WriterProperties,
+};
+
+use crate::flatten::{
+    Column, ColumnSelection, ColumnType, FlatSearch, FlightRow, RecoRow, Row, SearchRow, Value,
+};
+
+/// Money is stored as a decimal with 2 digits after the point, up to 10^16 euros.
+const MONEY_PRECISION: u8 = 18;
+const MONEY_SCALE: i8 = 2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ColumnarError {
+    #[error("Value {value:?} does not fit column {column:?}")]
+    UnexpectedValue { column: &'static str, value: Value },
+    #[error("Amount {value:?} of column {column:?} is not finite or exceeds the money precision")]
+    InvalidMoney { column: &'static str, value: f64 },
+    #[error("Arrow error: {0:?}")]
+    Arrow(#[from] ArrowError),
+    #[error("Parquet error: {0:?}")]
+    Parquet(#[from] ParquetError),
+    #[error("Failed to create {path:?}: {source:?}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+fn data_type(column_type: ColumnType) -> DataType {
+    match column_type {
+        ColumnType::String => DataType::Utf8,
+        // codes repeat a lot: each distinct one is only stored once
+        ColumnType::Code => {
+            DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8))
+        }
+        ColumnType::Integer => DataType::Int64,
+        ColumnType::Float => DataType::Float64,
+        ColumnType::Money => DataType::Decimal128(MONEY_PRECISION, MONEY_SCALE),
+        ColumnType::Boolean => DataType::Boolean,
+        ColumnType::Date => DataType::Date32,
+        // local times, as in the searches
+        ColumnType::DateTime => DataType::Timestamp(TimeUnit::Second, None),
+    }
+}
+
+/// Arrow schema of the selected columns.
+pub fn arrow_schema<R: Row>(selection: &ColumnSelection<R>) -> Schema {
+    Schema::new(
+        selection
+            .columns()
+            .iter()
+            .map(|column| Field::new(column.name, data_type(column.column_type), column.nullable))
+            .collect::<Vec<Field>>(),
+    )
+}
+
+/// An amount in cents, as long as it fits the money decimals.
+fn money_cents(column: &Column, value: f64) -> Result<i128, ColumnarError> {
+    let cents = (value * 10f64.powi(MONEY_SCALE as i32)).round();
+    if !cents.is_finite() || cents.abs() >= 10f64.powi(MONEY_PRECISION as i32) {
+        return Err(ColumnarError::InvalidMoney {
+            column: column.name,
+            value,
+        });
+    }
+    Ok(cents as i128)
+}
+
+fn days_since_epoch(date: NaiveDate) -> i32 {
+    (date - NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()).num_days() as i32
+}
+
+/// Builds the array of one column. `$builder` is filled with `$append` for the values matching
+/// `$pattern`, and with nulls for `Value::Null`.
+macro_rules! build_array {
+    ($column:expr, $values:expr, $builder:expr, $pattern:pat => $append:expr) => {{
+        let mut builder = $builder;
+        for value in $values {
+            match value {
+                $pattern => builder.append_value($append),
+                Value::Null => builder.append_null(),
+                _ => {
+                    return Err(ColumnarError::UnexpectedValue {
+                        column: $column.name,
+                        value: value.clone(),
+                    })
+                }
+            }
+        }
+        Arc::new(builder.finish()) as ArrayRef
+    }};
+}
+
+fn build_array<'a>(
+    column: &Column,
+    values: impl Iterator<Item = &'a Value>,
+) -> Result<ArrayRef, ColumnarError> {
+    Ok(match column.column_type {
+        ColumnType::String => {
+            build_array!(column, values, StringBuilder::new(), Value::String(s) => s)
+        }
+        ColumnType::Code => build_array!(
+            column,
+            values,
+            StringDictionaryBuilder::<Int32Type>::new(),
+            Value::String(s) => s
+        ),
+        ColumnType::Integer => {
+            build_array!(column, values, Int64Builder::new(), Value::Integer(i) => *i)
+        }
+        ColumnType::Float => {
+            build_array!(column, values, Float64Builder::new(), Value::Float(f) => *f)
+        }
+        ColumnType::Money => build_array!(
+            column,
+            values,
+            Decimal128Builder::new().with_precision_and_scale(MONEY_PRECISION, MONEY_SCALE)?,
+            Value::Float(f) => money_cents(column, *f)?
+        ),
+        ColumnType::Boolean => {
+            build_array!(column, values, BooleanBuilder::new(), Value::Boolean(b) => *b)
+        }
+        ColumnType::Date => build_array!(
+            column,
+            values,
+            Date32Builder::new(),
+            Value::Date(date) => days_since_epoch(*date)
+        ),
+        ColumnType::DateTime => build_array!(
+            column,
+            values,
+            TimestampSecondBuilder::new(),
+            Value::DateTime(datetime) => datetime.and_utc().timestamp()
+        ),
+    })
+}
+
+/// Converts rows into an Arrow record batch with the selected columns.
+pub fn record_batch<R: Row>(
+    selection: &ColumnSelection<R>,
+    schema: SchemaRef,
+    rows: &[R],
+) -> Result<RecordBatch, ColumnarError> {
+    let values: Vec<Vec<Value>> = rows.iter().map(|row| selection.select(row)).collect();
+    let arrays = selection
+        .columns()
+        .iter()
+        .enumerate()
+        .map(|(i, column)| build_array(column, values.iter().map(|row| &row[i])))
+        .collect::<Result<Vec<ArrayRef>, ColumnarError>>()?;
+    Ok(RecordBatch::try_new(schema, arrays)?)
+}
+
+#[derive(Clone, Debug)]
+pub struct ParquetOptions {
+    /// Rows buffered per partition before they are written as a record batch.
+    pub batch_size: usize,
+    pub compression: Compression,
+    /// Search dates with an open file. Beyond, the file of the earliest one is closed, and later
+    /// rows of that date go to a new file.
+    pub max_open_partitions: usize,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        ParquetOptions {
+            batch_size: 8192,
+            compression: Compression::SNAPPY,
+            max_open_partitions: 4,
+        }
+    }
+}
+
+/// Writers created by this process, to name their files apart.
+static NB_OF_WRITERS: AtomicUsize = AtomicUsize::new(0);
+
+struct Partition<R> {
+    writer: ArrowWriter<File>,
+    buffer: Vec<R>,
+}
+
+/// Writes rows to Parquet files partitioned by search date, in the Hive layout read by most tools:
+/// `<directory>/<table>/search_date=YYYY-MM-DD/part-<run id>-<n>.parquet`.
+/// Each writer has its own run id, so that files written before are never overwritten.
+/// A file stays open per search date until the writer is closed, or until
+/// `ParquetOptions::max_open_partitions` later dates are open.
+pub struct PartitionedParquetWriter<R: Row> {
+    directory: PathBuf,
+    selection: ColumnSelection<R>,
+    schema: SchemaRef,
+    options: ParquetOptions,
+    run_id: String,
+    partitions: BTreeMap<NaiveDate, Partition<R>>,
+    /// Files created per search date.
+    nb_of_parts: BTreeMap<NaiveDate, usize>,
+}
+
+impl<R: Row> PartitionedParquetWriter<R> {
+    pub fn new(
+        directory: impl Into<PathBuf>,
+        selection: ColumnSelection<R>,
+        options: ParquetOptions,
+    ) -> Self {
+        PartitionedParquetWriter {
+            directory: directory.into().join(R::TABLE),
+            schema: Arc::new(arrow_schema(&selection)),
+            selection,
+            options,
+            run_id: format!(
+                "{}-{}-{}",
+                Utc::now().format("%Y%m%dT%H%M%S"),
+                std::process::id(),
+                NB_OF_WRITERS.fetch_add(1, Ordering::Relaxed)
+            ),
+            partitions: BTreeMap::new(),
+            nb_of_parts: BTreeMap::new(),
+        }
+    }
+
+    fn open_partition(&mut self, search_date: NaiveDate) -> Result<Partition<R>, ColumnarError> {
+        let partition_directory = self
+            .directory
+            .join(format!("search_date={}", search_date.format("%Y-%m-%d")));
+        fs::create_dir_all(&partition_directory).map_err(|source| ColumnarError::Io {
+            path: partition_directory.clone(),
+            source,
+        })?;
+        let part_nb = self.nb_of_parts.entry(search_date).or_default();
+        let path = partition_directory.join(format!("part-{}-{part_nb}.parquet", self.run_id));
+        *part_nb += 1;
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|source| ColumnarError::Io { path, source })?;
+
+        let properties = WriterProperties::builder()
+            .set_compression(self.options.compression)
+            .build();
+        Ok(Partition {
+            writer: ArrowWriter::try_new(file, self.schema.clone(), Some(properties))?,
+            buffer: Vec::with_capacity(self.options.batch_size),
+        })
+    }
+
+    fn flush_partition(
+        selection: &ColumnSelection<R>,
+        schema: &SchemaRef,
+        partition: &mut Partition<R>,
+    ) -> Result<(), ColumnarError> {
+        if partition.buffer.is_empty() {
+            return Ok(());
+        }
+        let batch = record_batch(selection, schema.clone(), &partition.buffer)?;
+        partition.writer.write(&batch)?;
+        partition.buffer.clear();
+        Ok(())
+    }
+
+    pub fn write(
+        &mut self,
+        search_date: NaiveDate,
+        rows: impl IntoIterator<Item = R>,
+    ) -> Result<(), ColumnarError> {
+        if !self.partitions.contains_key(&search_date) {
+            while self.partitions.len() >= self.options.max_open_partitions.max(1) {
+                let (_, partition) = self.partitions.pop_first().unwrap();
+                self.close_partition(partition)?;
+            }
+            let partition = self.open_partition(search_date)?;
+            self.partitions.insert(search_date, partition);
+        }
+        // just inserted
+        let partition = self.partitions.get_mut(&search_date).unwrap();
+        for row in rows {
+            partition.buffer.push(row);
+            if partition.buffer.len() >= self.options.batch_size {
+                Self::flush_partition(&self.selection, &self.schema, partition)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn close_partition(&self, mut partition: Partition<R>) -> Result<(), ColumnarError> {
+        Self::flush_partition(&self.selection, &self.schema, &mut partition)?;
+        partition.writer.close()?;
+        Ok(())
+    }
+
+    /// Writes the buffered rows and the Parquet footers. Files are unreadable until then.
+    pub fn close(mut self) -> Result<(), ColumnarError> {
+        for (_, partition) in std::mem::take(&mut self.partitions) {
+            self.close_partition(partition)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes flattened searches to the `db_searches`, `db_recos` and `db_flights` tables of a
+/// directory, each partitioned by search date.
+pub struct FlatSearchParquetWriter {
+    searches: PartitionedParquetWriter<SearchRow>,
+    recos: PartitionedParquetWriter<RecoRow>,
+    flights: PartitionedParquetWriter<FlightRow>,
+}
+
+impl FlatSearchParquetWriter {
+    /// Writes all the columns.
+    pub fn new(directory: impl Into<PathBuf>, options: ParquetOptions) -> Self {
+        Self::with_selections(
+            directory,
+            ColumnSelection::all(),
+            ColumnSelection::all(),
+            ColumnSelection::all(),
+            options,
+        )
+    }
+
+    pub fn with_selections(
+        directory: impl Into<PathBuf>,
+        search_columns: ColumnSelection<SearchRow>,
+        reco_columns: ColumnSelection<RecoRow>,
+        flight_columns: ColumnSelection<FlightRow>,
+        options: ParquetOptions,
+    ) -> Self {
+        let directory = directory.into();
+        FlatSearchParquetWriter {
+            searches: PartitionedParquetWriter::new(&directory, search_columns, options.clone()),
+            recos: PartitionedParquetWriter::new(&directory, reco_columns, options.clone()),
+            flights: PartitionedParquetWriter::new(&directory, flight_columns, options),
+        }
+    }
+
+    pub fn write(&mut self, flat_search: FlatSearch) -> Result<(), ColumnarError> {
+        let search_date = flat_search.search.search_date;
+        self.searches.write(search_date, [flat_search.search])?;
+        self.recos.write(search_date, flat_search.recos)?;
+        self.flights.write(search_date, flat_search.flights)
+    }
+
+    pub fn close(self) -> Result<(), ColumnarError> {
+        self.searches.close()?;
+        self.recos.close()?;
+        self.flights.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{enrich_to_rows, sample_search};
+
+    #[test]
+    fn test_invalid_money() {
+        use arrow_array::{Array, Decimal128Array};
+
+        let column = Column {
+            name: "price_EUR",
+            column_type: ColumnType::Money,
+            nullable: false,
+        };
+        let array = |value: f64| build_array(&column, [Value::Float(value)].iter());
+
+        let prices = array(12.345).unwrap();
+        let prices = prices.as_any().downcast_ref::<Decimal128Array>().unwrap();
+        assert_eq!(prices.value_as_string(0), "12.35");
+        assert!(array(1e15).is_ok());
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e16, -1e16] {
+            assert!(matches!(
+                array(value),
+                Err(ColumnarError::InvalidMoney { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parquet_output() {
+        use arrow_array::{Array, Date32Array, Decimal128Array, DictionaryArray};
+        use arrow_schema::DataType;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let rows_at = |search_date: &str| {
+            let mut search = sample_search();
+            search["search_date"] = search_date.into();
+            enrich_to_rows(search)
+        };
+
+        let directory = std::env::temp_dir().join(format!("enriched-{}", std::process::id()));
+        let options = ParquetOptions {
+            batch_size: 3,
+            ..ParquetOptions::default()
+        };
+        let mut writer = FlatSearchParquetWriter::new(&directory, options.clone());
+        writer.write(rows_at("2021-11-17")).unwrap();
+        writer.close().unwrap();
+
+        let files = |table: &str, search_date: &str| {
+            let mut paths: Vec<_> = std::fs::read_dir(
+                directory
+                    .join(table)
+                    .join(format!("search_date={search_date}")),
+            )
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+            paths.sort();
+            paths
+        };
+        let read_file = |path: &std::path::Path| {
+            let reader =
+                ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(path).unwrap())
+                    .unwrap()
+                    .build()
+                    .unwrap();
+            // batches of the writer end up in the same row group
+            let mut batches: Vec<_> = reader.map(|batch| batch.unwrap()).collect();
+            assert_eq!(batches.len(), 1);
+            batches.remove(0)
+        };
+        let read = |table: &str| {
+            let files = files(table, "2021-11-17");
+            assert_eq!(files.len(), 1);
+            read_file(&files[0])
+        };
+
+        let searches = read("db_searches");
+        assert_eq!(searches.num_rows(), 1);
+        let search_date = searches.column_by_name("search_date").unwrap();
+        let search_date = search_date.as_any().downcast_ref::<Date32Array>().unwrap();
+        assert_eq!(
+            search_date.value_as_date(0).unwrap().to_string(),
+            "2021-11-17"
+        );
+
+        let recos = read("db_recos");
+        assert_eq!(recos.num_rows(), 3);
+        let prices = recos.column_by_name("price_EUR").unwrap();
+        let prices = prices.as_any().downcast_ref::<Decimal128Array>().unwrap();
+        assert_eq!(prices.value_as_string(0), "578.72");
+        let airlines = recos.column_by_name("main_marketing_airline").unwrap();
+        assert!(matches!(airlines.data_type(), DataType::Dictionary(_, _)));
+        let airlines = airlines
+            .as_any()
+            .downcast_ref::<DictionaryArray<arrow_array::types::Int32Type>>()
+            .unwrap();
+        assert_eq!(airlines.values().len(), 2);
+
+        assert_eq!(read("db_flights").num_rows(), 7);
+
+        // a later run adds files, and earlier dates are closed once too many are open
+        let mut writer = FlatSearchParquetWriter::new(
+            &directory,
+            ParquetOptions {
+                max_open_partitions: 1,
+                ..options
+            },
+        );
+        writer.write(rows_at("2021-11-17")).unwrap();
+        writer.write(rows_at("2021-11-16")).unwrap();
+        writer.write(rows_at("2021-11-17")).unwrap();
+        writer.close().unwrap();
+        let files = files("db_recos", "2021-11-17");
+        assert_eq!(files.len(), 3);
+        for file in &files {
+            assert_eq!(read_file(file).num_rows(), 3);
+        }
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
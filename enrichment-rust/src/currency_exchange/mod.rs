@@ -2,9 +2,27 @@ use csv::Reader;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
+use std::io::Read;
+use std::path::Path;
 use std::str::FromStr;
 use strum_macros::EnumString;
 
+#[derive(Debug, thiserror::Error)]
+pub enum ExchangeRatesError {
+    #[error("Failed to open rates: {0:?}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to read rates: {0:?}")]
+    Csv(#[from] csv::Error),
+    #[error("No rates in the file")]
+    NoRates,
+    #[error("Unknown currency {0:?}")]
+    UnknownCurrency(String),
+    #[error("Invalid rate {rate:?} for {currency:?}")]
+    InvalidRate { currency: Currency, rate: String },
+    #[error("No rate for {0:?}")]
+    MissingRate(Currency),
+}
+
 #[derive(EnumString, Serialize, Deserialize, Clone, Copy, Hash, Eq, PartialEq, Debug)]
 pub enum Currency {
     EUR,
@@ -46,46 +64,87 @@ type Rate = f64;
 
 pub struct ExchangeRates {
     rates: HashMap<Currency, Rate>,
-    _record_date: String,
+    record_date: String,
 }
 
 impl ExchangeRates {
     pub fn new() -> Self {
-        let file = File::open("src/currency_exchange/eurofxref.csv").unwrap();
-        let mut reader = Reader::from_reader(file);
+        Self::from_file("src/currency_exchange/eurofxref.csv").unwrap()
+    }
+
+    /// Loads the rates of the last line of an ECB `eurofxref.csv` file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ExchangeRatesError> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, ExchangeRatesError> {
+        let mut reader = Reader::from_reader(reader);
 
         let mut rates = HashMap::new();
 
-        let header = reader.headers().unwrap().clone();
+        let header = reader.headers()?.clone();
         let header = header.into_iter().collect::<Vec<_>>();
 
-        let last_record = reader.records().last().unwrap().unwrap();
+        let last_record = reader
+            .records()
+            .last()
+            .ok_or(ExchangeRatesError::NoRates)??;
         let mut last_record = last_record.iter().enumerate();
 
-        let record_date = last_record.next().unwrap().1.to_string();
+        let record_date = last_record
+            .next()
+            .ok_or(ExchangeRatesError::NoRates)?
+            .1
+            .to_string();
 
         for (currency, rate) in last_record {
-            let currency_name = header[currency].trim();
+            let currency_name = header.get(currency).map_or("", |name| name.trim());
             if currency_name.is_empty() {
                 continue;
             }
 
-            let currency = Currency::from_str(currency_name).unwrap();
-            let rate = rate.trim().parse().unwrap();
+            let currency = Currency::from_str(currency_name)
+                .map_err(|_| ExchangeRatesError::UnknownCurrency(currency_name.to_string()))?;
+            let rate = rate
+                .trim()
+                .parse()
+                .ok()
+                .filter(|rate: &Rate| rate.is_finite() && *rate > 0.0)
+                .ok_or_else(|| ExchangeRatesError::InvalidRate {
+                    currency,
+                    rate: rate.to_string(),
+                })?;
             rates.insert(currency, rate);
         }
-
-        ExchangeRates {
-            rates,
-            _record_date: record_date,
+        if rates.is_empty() {
+            return Err(ExchangeRatesError::NoRates);
         }
+
+        Ok(ExchangeRates { rates, record_date })
     }
 
-    pub fn to_euros(&self, amount: f64, currency: &Currency) -> f64 {
+    /// Units of `currency` for one euro, `None` when the currency has no rate.
+    pub fn get_rate(&self, currency: &Currency) -> Option<Rate> {
         match currency {
-            Currency::EUR => amount,
-            // TODO: handle missing rates
-            _ => amount / self.rates.get(currency).unwrap(),
+            Currency::EUR => Some(1.0),
+            _ => self.rates.get(currency).copied(),
+        }
+    }
+
+    /// Currencies with a rate, other than the euro.
+    pub fn currencies(&self) -> impl Iterator<Item = &Currency> {
+        self.rates.keys()
+    }
+
+    /// Date of the rates, as in the reference file.
+    pub fn record_date(&self) -> &str {
+        &self.record_date
+    }
+
+    pub fn to_euros(&self, amount: f64, currency: &Currency) -> Result<f64, ExchangeRatesError> {
+        match self.get_rate(currency) {
+            Some(rate) => Ok(amount / rate),
+            None => Err(ExchangeRatesError::MissingRate(*currency)),
         }
     }
 }
@@ -104,6 +163,32 @@ mod tests {
     fn test_from_euros_to_euros() {
         let exchange_rates = ExchangeRates::new();
 
-        assert_eq!(exchange_rates.to_euros(100.0, &Currency::EUR), 100.0);
+        assert_eq!(
+            exchange_rates.to_euros(100.0, &Currency::EUR).unwrap(),
+            100.0
+        );
+    }
+
+    #[test]
+    fn test_invalid_rates() {
+        let rates = "Date, USD, GBP, \n19 November 2021, 1.1271, 0.83928, \n";
+        let exchange_rates = ExchangeRates::from_reader(rates.as_bytes()).unwrap();
+        assert_eq!(exchange_rates.get_rate(&Currency::GBP), Some(0.83928));
+        assert_eq!(exchange_rates.get_rate(&Currency::JPY), None);
+        assert!(matches!(
+            exchange_rates.to_euros(100.0, &Currency::JPY),
+            Err(ExchangeRatesError::MissingRate(Currency::JPY))
+        ));
+
+        for rates in [
+            "Date, USD\n",
+            "Date, USD\n19 November 2021, N/A\n",
+            "Date, XXX\n19 November 2021, 1.0\n",
+        ] {
+            assert!(
+                ExchangeRates::from_reader(rates.as_bytes()).is_err(),
+                "{rates}"
+            );
+        }
     }
 }
@@ -0,0 +1,215 @@
+use std::io::{BufRead, Lines};
+
+use serde_json::{Map, Value};
+
+/// Columns of a dump line, before the flights. As in `kafkaFakeStreamer.py`, a line is a reco.
+const RECO_LAYOUT: &[&str] = &[
+    "version_nb",
+    "search_id",
+    "search_country",
+    "search_date",
+    "search_time",
+    "origin_city",
+    "destination_city",
+    "request_dep_date",
+    "request_return_date",
+    "passengers_string",
+    "currency",
+    "price",
+    "taxes",
+    "fees",
+    "nb_of_flights",
+];
+
+/// Columns of each of the `nb_of_flights` flights ending a dump line.
+const FLIGHT_LAYOUT: &[&str] = &[
+    "dep_airport",
+    "dep_date",
+    "dep_time",
+    "arr_airport",
+    "arr_date",
+    "arr_time",
+    "operating_airline",
+    "marketing_airline",
+    "flight_nb",
+    "cabin",
+];
+
+/// Columns of the reco layout shared by all the recos of a search.
+const SEARCH_FIELDS: &[&str] = &[
+    "version_nb",
+    "search_id",
+    "search_country",
+    "search_date",
+    "search_time",
+    "origin_city",
+    "destination_city",
+    "request_dep_date",
+    "request_return_date",
+    "passengers_string",
+    "currency",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum DumpError {
+    #[error("Failed to read dump: {0:?}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid line {line_nb}: {reason}")]
+    InvalidLine { line_nb: usize, reason: String },
+    #[error("Invalid JSON on line {line_nb}: {source:?}")]
+    InvalidJson {
+        line_nb: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+/// Splits a `^`-separated dump line into its search fields and its reco.
+fn decode_line(line: &str, line_nb: usize) -> Result<(Map<String, Value>, Value), DumpError> {
+    let invalid = |reason: String| DumpError::InvalidLine { line_nb, reason };
+
+    let columns: Vec<&str> = line.trim_end().split('^').collect();
+    if columns.len() < RECO_LAYOUT.len() {
+        return Err(invalid(format!(
+            "{} columns, expected at least {}",
+            columns.len(),
+            RECO_LAYOUT.len()
+        )));
+    }
+    let nb_of_flights: usize = columns[RECO_LAYOUT.len() - 1]
+        .parse()
+        .map_err(|_| invalid("nb_of_flights is not a number".to_string()))?;
+    let flight_columns = &columns[RECO_LAYOUT.len()..];
+    if flight_columns.len() < nb_of_flights * FLIGHT_LAYOUT.len() {
+        return Err(invalid(format!(
+            "missing columns for {nb_of_flights} flights"
+        )));
+    }
+
+    let mut search = Map::new();
+    let mut reco = Map::new();
+    for (key, value) in RECO_LAYOUT.iter().zip(&columns) {
+        let fields = if SEARCH_FIELDS.contains(key) {
+            &mut search
+        } else {
+            &mut reco
+        };
+        fields.insert(key.to_string(), Value::from(*value));
+    }
+    reco.insert("nb_of_flights".to_string(), Value::from(nb_of_flights));
+    let flights = flight_columns
+        .chunks(FLIGHT_LAYOUT.len())
+        .take(nb_of_flights)
+        .map(|flight| {
+            FLIGHT_LAYOUT
+                .iter()
+                .zip(flight)
+                .map(|(key, value)| (key.to_string(), Value::from(*value)))
+                .collect::<Map<String, Value>>()
+        })
+        .map(Value::Object)
+        .collect();
+    reco.insert("flights".to_string(), Value::Array(flights));
+
+    Ok((search, Value::Object(reco)))
+}
+
+/// Reads the `^`-separated CSV dump of the travel data sample, one reco per line, into search
+/// JSONs. Consecutive recos with the same `search_id` make a search.
+pub struct CsvDumpReader<R: BufRead> {
+    lines: Lines<R>,
+    line_nb: usize,
+    current: Option<Map<String, Value>>,
+}
+
+impl<R: BufRead> CsvDumpReader<R> {
+    pub fn new(reader: R) -> Self {
+        CsvDumpReader {
+            lines: reader.lines(),
+            line_nb: 0,
+            current: None,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CsvDumpReader<R> {
+    type Item = Result<Value, DumpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => return Some(Err(e.into())),
+                None => return self.current.take().map(|search| Ok(Value::Object(search))),
+            };
+            self.line_nb += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (mut search, reco) = match decode_line(&line, self.line_nb) {
+                Ok(decoded) => decoded,
+                Err(e) => return Some(Err(e)),
+            };
+
+            match &mut self.current {
+                Some(current) if current["search_id"] == search["search_id"] => {
+                    // the search has a "recos" array since its first line
+                    current["recos"].as_array_mut().unwrap().push(reco);
+                }
+                _ => {
+                    search.insert("recos".to_string(), Value::Array(vec![reco]));
+                    if let Some(previous) = self.current.replace(search) {
+                        return Some(Ok(Value::Object(previous)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reads one search JSON per line, skipping empty lines.
+pub fn read_ndjson<R: BufRead>(reader: R) -> impl Iterator<Item = Result<Value, DumpError>> {
+    reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(index, line)| {
+            serde_json::from_str(&line?).map_err(|source| DumpError::InvalidJson {
+                line_nb: index + 1,
+                source,
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_dump() {
+        let dump = "\
+1.0^S1^FR^2021-11-17^11:48:39^PAR^LIS^2021-12-17^^ADT=1^EUR^250.50^50.00^0.00^1^ORY^2021-12-17^07:00^LIS^2021-12-17^08:40^^TP^433^M
+1.0^S1^FR^2021-11-17^11:48:39^PAR^LIS^2021-12-17^^ADT=1^EUR^199.99^40.00^0.00^1^CDG^2021-12-17^10:00^LIS^2021-12-17^11:40^TP^TP^431^M
+
+1.0^S2^FR^2021-11-17^11:50:00^PAR^AMS^2021-12-20^^ADT=1^EUR^120.00^30.00^0.00^1^CDG^2021-12-20^20:55^AMS^2021-12-20^22:10^KL^KL^1246^M
+";
+        let searches: Vec<Value> = CsvDumpReader::new(dump.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(searches.len(), 2);
+        assert_eq!(searches[0]["search_id"], "S1");
+        assert_eq!(searches[0]["request_return_date"], "");
+        assert_eq!(searches[0]["recos"].as_array().unwrap().len(), 2);
+        assert_eq!(searches[0]["recos"][1]["price"], "199.99");
+        assert_eq!(searches[0]["recos"][0]["flights"][0]["flight_nb"], "433");
+        assert_eq!(searches[1]["recos"][0]["flights"][0]["arr_airport"], "AMS");
+
+        let truncated =
+            "1.0^S3^FR^2021-11-17^11:48:39^PAR^LIS^2021-12-17^^ADT=1^EUR^250.50^50.00^0.00^2^ORY";
+        assert!(matches!(
+            CsvDumpReader::new(truncated.as_bytes()).next(),
+            Some(Err(DumpError::InvalidLine { line_nb: 1, .. }))
+        ));
+    }
+}
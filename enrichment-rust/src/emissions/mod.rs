@@ -0,0 +1,133 @@
+use std::io::Read;
+
+use crate::cabin::Cabin;
+
+/// kg of CO2 emitted per kg of jet fuel burnt.
+const CO2_PER_KG_FUEL: f64 = 3.16;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmissionsError {
+    #[error("Failed to read fuel bands: {0:?}")]
+    Csv(#[from] csv::Error),
+    #[error("At least two fuel bands are needed, found {0}")]
+    TooFewBands(usize),
+}
+
+#[derive(serde::Deserialize)]
+struct FuelBand {
+    distance_km: f64,
+    fuel_kg_per_pax: f64,
+}
+
+/// Per-passenger CO2 estimates, following the ICAO Carbon Emissions Calculator methodology:
+/// the great-circle distance is corrected for routing and holding, the fuel burnt by an economy
+/// passenger is interpolated between distance bands (averaged over the aircraft mix, load factors
+/// and passenger/freight split), then weighted by cabin.
+pub struct EmissionsModel {
+    bands: Vec<FuelBand>,
+}
+
+impl EmissionsModel {
+    /// Uses the fuel bands of `fuel_bands.csv`, embedded in the crate.
+    pub fn new() -> Self {
+        Self::from_reader(include_str!("fuel_bands.csv").as_bytes())
+            .expect("The embedded fuel bands are valid")
+    }
+
+    /// Loads `distance_km,fuel_kg_per_pax` bands from any reader.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, EmissionsError> {
+        let mut bands: Vec<FuelBand> = csv::Reader::from_reader(reader)
+            .deserialize()
+            .collect::<Result<_, _>>()?;
+        bands.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+        if bands.len() < 2 {
+            return Err(EmissionsError::TooFewBands(bands.len()));
+        }
+
+        Ok(EmissionsModel { bands })
+    }
+
+    /// ICAO adds these to the great-circle distance to account for non-direct routings and holding.
+    fn corrected_distance_km(distance_km: f64) -> f64 {
+        if distance_km < 550.0 {
+            distance_km + 50.0
+        } else if distance_km < 5500.0 {
+            distance_km + 100.0
+        } else {
+            distance_km + 125.0
+        }
+    }
+
+    /// Fuel burnt by an economy passenger, linearly interpolated between the two surrounding bands,
+    /// or extrapolated from the first or last two.
+    fn fuel_kg_per_pax(&self, distance_km: f64) -> f64 {
+        let upper = self
+            .bands
+            .iter()
+            .position(|band| band.distance_km >= distance_km)
+            .unwrap_or(self.bands.len() - 1)
+            .max(1);
+        let (low, high) = (&self.bands[upper - 1], &self.bands[upper]);
+
+        let slope =
+            (high.fuel_kg_per_pax - low.fuel_kg_per_pax) / (high.distance_km - low.distance_km);
+        (low.fuel_kg_per_pax + slope * (distance_km - low.distance_km)).max(0.0)
+    }
+
+    /// kg of CO2 per passenger for a flight of `distance_km` (great-circle) in `cabin`.
+    /// Unknown cabins are counted as economy.
+    pub fn co2_kg_per_pax(&self, distance_km: u64, cabin: Option<Cabin>) -> f64 {
+        let distance_km = Self::corrected_distance_km(distance_km as f64);
+        self.fuel_kg_per_pax(distance_km) * CO2_PER_KG_FUEL * cabin_weight(cabin)
+    }
+}
+
+impl Default for EmissionsModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Premium seats take more room on the aircraft, hence a bigger share of its emissions.
+fn cabin_weight(cabin: Option<Cabin>) -> f64 {
+    match cabin {
+        Some(Cabin::PremiumEconomy) => 1.5,
+        Some(Cabin::Business) => 2.9,
+        Some(Cabin::First) => 4.0,
+        Some(Cabin::Economy) | None => 1.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_co2_estimates() {
+        let emissions_model = EmissionsModel::new();
+
+        // CDG-LIS: 1452 km great-circle, 1552 km corrected
+        let economy = emissions_model.co2_kg_per_pax(1452, Some(Cabin::Economy));
+        assert!((economy - 32.728 * CO2_PER_KG_FUEL).abs() < 0.01);
+        assert!(emissions_model.co2_kg_per_pax(1452, Some(Cabin::Business)) > 2.0 * economy);
+        assert!(
+            emissions_model.co2_kg_per_pax(20000, None)
+                > emissions_model.co2_kg_per_pax(16000, None)
+        );
+        assert!(emissions_model.co2_kg_per_pax(0, None) > 0.0);
+    }
+
+    #[test]
+    fn test_invalid_fuel_bands() {
+        let one_band = "distance_km,fuel_kg_per_pax\n500,10\n";
+        assert!(matches!(
+            EmissionsModel::from_reader(one_band.as_bytes()),
+            Err(EmissionsError::TooFewBands(1))
+        ));
+        let invalid = "distance_km,fuel_kg_per_pax\n500,10\n1000,a lot\n";
+        assert!(matches!(
+            EmissionsModel::from_reader(invalid.as_bytes()),
+            Err(EmissionsError::Csv(_))
+        ));
+    }
+}
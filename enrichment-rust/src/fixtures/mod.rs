@@ -0,0 +1,52 @@
+//! Sample reference data and search shared by the tests of the modules.
+
+use crate::{
+    airlines, currency_exchange, emissions, enrich_json_to_rows, enrich_json_to_search,
+    flatten::FlatSearch, neobase, reference_data::ReferenceData,
+    search::enriched_search::EnrichedSearch, search::Search, EnrichOptions,
+};
+
+/// The locations of the sample search. Also used by the benchmarks.
+pub const NEOBASE_SAMPLE: &str = include_str!("neobase_sample.csv");
+
+/// A round trip with three recos. Also used by the benchmarks.
+pub fn sample_search() -> serde_json::Value {
+    serde_json::from_str(include_str!("sample_search.json")).unwrap()
+}
+
+pub fn reference_data() -> ReferenceData {
+    ReferenceData::new(
+        neobase::Locations::from_reader(NEOBASE_SAMPLE.as_bytes()),
+        currency_exchange::ExchangeRates::new(),
+        emissions::EmissionsModel::new(),
+        airlines::Airlines::new(),
+    )
+}
+
+/// Enriches a search with the sample reference data, into rows.
+pub fn enrich_to_rows(search: serde_json::Value) -> FlatSearch {
+    let reference_data = reference_data();
+    enrich_json_to_rows(
+        search,
+        &reference_data.locations,
+        &reference_data.exchange_rates,
+        &reference_data.emissions_model,
+        &reference_data.airlines,
+        EnrichOptions::default(),
+    )
+    .unwrap()
+}
+
+/// Enriches a search with the sample reference data, typed.
+pub fn enrich_to_search(search: serde_json::Value) -> (Search<'static>, EnrichedSearch) {
+    let reference_data = reference_data();
+    enrich_json_to_search(
+        search,
+        &reference_data.locations,
+        &reference_data.exchange_rates,
+        &reference_data.emissions_model,
+        &reference_data.airlines,
+        EnrichOptions::default(),
+    )
+    .unwrap()
+}
@@ -0,0 +1,611 @@
+use std::marker::PhantomData;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+
+use crate::search::{enriched_search::EnrichedSearch, Search};
+
+/// Type of a column, as a database or a columnar file would store it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    /// Free text, like identifiers.
+    String,
+    /// Short code out of a small set, like airports, airlines or trip types.
+    Code,
+    Integer,
+    Float,
+    /// Amount in euros, with 2 decimals.
+    Money,
+    Boolean,
+    Date,
+    DateTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Column {
+    /// Same name as the field of the enriched JSON.
+    pub name: &'static str,
+    pub column_type: ColumnType,
+    pub nullable: bool,
+}
+
+const fn column(name: &'static str, column_type: ColumnType) -> Column {
+    Column {
+        name,
+        column_type,
+        nullable: false,
+    }
+}
+
+const fn nullable(name: &'static str, column_type: ColumnType) -> Column {
+    Column {
+        name,
+        column_type,
+        nullable: true,
+    }
+}
+
+/// Value of a cell.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
+}
+
+/// Dates are written as "YYYY-MM-DD", datetimes as "YYYY-MM-DDTHH:MM:SS".
+impl From<Value> for serde_json::Value {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => serde_json::Value::Null,
+            Value::String(s) => s.into(),
+            Value::Integer(i) => i.into(),
+            Value::Float(f) => f.into(),
+            Value::Boolean(b) => b.into(),
+            Value::Date(date) => date.format("%Y-%m-%d").to_string().into(),
+            Value::DateTime(datetime) => datetime.format("%Y-%m-%dT%H:%M:%S").to_string().into(),
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value::Integer(value as i64)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Boolean(value)
+    }
+}
+
+impl From<NaiveDate> for Value {
+    fn from(value: NaiveDate) -> Self {
+        Value::Date(value)
+    }
+}
+
+impl From<NaiveDateTime> for Value {
+    fn from(value: NaiveDateTime) -> Self {
+        Value::DateTime(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+/// A flat record with a fixed schema.
+pub trait Row {
+    /// Name of the table (or topic) the rows go to.
+    const TABLE: &'static str;
+
+    fn schema() -> &'static [Column];
+
+    /// Values in the order of the schema.
+    fn values(&self) -> Vec<Value>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FlattenError {
+    #[error("Unknown column {column:?} in table {table:?}")]
+    UnknownColumn { table: &'static str, column: String },
+}
+
+/// Subset of the columns of a row type, in the requested order.
+pub struct ColumnSelection<R: Row> {
+    indices: Vec<usize>,
+    row_type: PhantomData<R>,
+}
+
+impl<R: Row> Clone for ColumnSelection<R> {
+    fn clone(&self) -> Self {
+        ColumnSelection {
+            indices: self.indices.clone(),
+            row_type: PhantomData,
+        }
+    }
+}
+
+impl<R: Row> ColumnSelection<R> {
+    pub fn all() -> Self {
+        ColumnSelection {
+            indices: (0..R::schema().len()).collect(),
+            row_type: PhantomData,
+        }
+    }
+
+    pub fn of(names: &[&str]) -> Result<Self, FlattenError> {
+        let indices = names
+            .iter()
+            .map(|name| {
+                R::schema()
+                    .iter()
+                    .position(|column| column.name == *name)
+                    .ok_or(FlattenError::UnknownColumn {
+                        table: R::TABLE,
+                        column: name.to_string(),
+                    })
+            })
+            .collect::<Result<Vec<usize>, FlattenError>>()?;
+        Ok(ColumnSelection {
+            indices,
+            row_type: PhantomData,
+        })
+    }
+
+    pub fn columns(&self) -> Vec<Column> {
+        self.indices.iter().map(|i| R::schema()[*i]).collect()
+    }
+
+    pub fn select(&self, row: &R) -> Vec<Value> {
+        let values = row.values();
+        self.indices.iter().map(|i| values[*i].clone()).collect()
+    }
+
+    /// The selected columns as a JSON object, like the records of the `db_searches` and `db_recos`
+    /// topics.
+    pub fn to_json(&self, row: &R) -> serde_json::Map<String, serde_json::Value> {
+        self.columns()
+            .iter()
+            .zip(self.select(row))
+            .map(|(column, value)| (column.name.to_string(), value.into()))
+            .collect()
+    }
+}
+
+/// Code of an enum, as it appears in the enriched JSON.
+fn code<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(code)) => code,
+        _ => String::new(),
+    }
+}
+
+fn round_money(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+pub struct SearchRow {
+    pub search_id: String,
+    pub search_country: String,
+    pub search_date: NaiveDate,
+    pub request_dep_date: NaiveDate,
+    pub request_return_date: Option<NaiveDate>,
+    pub advance_purchase: u64,
+    pub stay_duration: Option<u64>,
+    pub trip_type: String,
+    pub ond: String,
+    pub origin_city: String,
+    pub destination_city: String,
+    pub origin_country: String,
+    pub destination_country: String,
+    pub geo: Option<String>,
+    pub ond_distance: u64,
+    pub currency: String,
+    pub nb_of_recos: u64,
+    pub min_price_eur: Option<f64>,
+    pub max_price_eur: Option<f64>,
+    pub median_price_eur: Option<f64>,
+    pub cheapest_direct_price_eur: Option<f64>,
+    pub nb_of_airlines: u64,
+}
+
+impl SearchRow {
+    /// Columns of the `db_searches` records written by `db-writer.py`.
+    pub const DB_SEARCHES_COLUMNS: &'static [&'static str] = &[
+        "search_id",
+        "search_country",
+        "search_date",
+        "request_dep_date",
+        "advance_purchase",
+        "stay_duration",
+        "trip_type",
+        "OnD",
+    ];
+}
+
+const SEARCH_SCHEMA: &[Column] = &[
+    column("search_id", ColumnType::String),
+    column("search_country", ColumnType::Code),
+    column("search_date", ColumnType::Date),
+    column("request_dep_date", ColumnType::Date),
+    nullable("request_return_date", ColumnType::Date),
+    column("advance_purchase", ColumnType::Integer),
+    nullable("stay_duration", ColumnType::Integer),
+    column("trip_type", ColumnType::Code),
+    column("OnD", ColumnType::Code),
+    column("origin_city", ColumnType::Code),
+    column("destination_city", ColumnType::Code),
+    column("origin_country", ColumnType::Code),
+    column("destination_country", ColumnType::Code),
+    nullable("geo", ColumnType::Code),
+    column("OnD_distance", ColumnType::Integer),
+    column("currency", ColumnType::Code),
+    column("nb_of_recos", ColumnType::Integer),
+    nullable("min_price_EUR", ColumnType::Money),
+    nullable("max_price_EUR", ColumnType::Money),
+    nullable("median_price_EUR", ColumnType::Money),
+    nullable("cheapest_direct_price_EUR", ColumnType::Money),
+    column("nb_of_airlines", ColumnType::Integer),
+];
+
+impl Row for SearchRow {
+    const TABLE: &'static str = "db_searches";
+
+    fn schema() -> &'static [Column] {
+        SEARCH_SCHEMA
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            self.search_id.clone().into(),
+            self.search_country.clone().into(),
+            self.search_date.into(),
+            self.request_dep_date.into(),
+            self.request_return_date.into(),
+            self.advance_purchase.into(),
+            self.stay_duration.into(),
+            self.trip_type.clone().into(),
+            self.ond.clone().into(),
+            self.origin_city.clone().into(),
+            self.destination_city.clone().into(),
+            self.origin_country.clone().into(),
+            self.destination_country.clone().into(),
+            self.geo.clone().into(),
+            self.ond_distance.into(),
+            self.currency.clone().into(),
+            self.nb_of_recos.into(),
+            self.min_price_eur.into(),
+            self.max_price_eur.into(),
+            self.median_price_eur.into(),
+            self.cheapest_direct_price_eur.into(),
+            self.nb_of_airlines.into(),
+        ]
+    }
+}
+
+pub struct RecoRow {
+    pub search_id: String,
+    pub reco_index: u64,
+    pub nb_of_flights: u64,
+    pub price_eur: f64,
+    pub taxes_eur: f64,
+    pub fees_eur: f64,
+    pub main_marketing_airline: String,
+    pub main_operating_airline: String,
+    pub main_airline_alliance: Option<String>,
+    pub main_cabin: String,
+    /// Normalized cabin (M, W, C, F), `None` when the cabin code is unknown.
+    pub main_cabin_class: Option<String>,
+    pub trip_type: String,
+    pub flown_distance: u64,
+    pub co2_kg: f64,
+    pub circuity: f64,
+    pub is_direct: bool,
+    pub is_lcc: bool,
+    pub is_codeshare: bool,
+    pub is_interline: bool,
+    pub is_duplicate: bool,
+    pub price_rank: u64,
+    pub itinerary_fingerprint: String,
+}
+
+impl RecoRow {
+    /// Columns of the `db_recos` records written by `db-writer.py`.
+    pub const DB_RECOS_COLUMNS: &'static [&'static str] = &[
+        "nb_of_flights",
+        "price_EUR",
+        "main_marketing_airline",
+        "main_operating_airline",
+        "search_id",
+    ];
+}
+
+const RECO_SCHEMA: &[Column] = &[
+    column("search_id", ColumnType::String),
+    column("reco_index", ColumnType::Integer),
+    column("nb_of_flights", ColumnType::Integer),
+    column("price_EUR", ColumnType::Money),
+    column("taxes_EUR", ColumnType::Money),
+    column("fees_EUR", ColumnType::Money),
+    column("main_marketing_airline", ColumnType::Code),
+    column("main_operating_airline", ColumnType::Code),
+    nullable("main_airline_alliance", ColumnType::Code),
+    column("main_cabin", ColumnType::Code),
+    nullable("main_cabin_class", ColumnType::Code),
+    column("trip_type", ColumnType::Code),
+    column("flown_distance", ColumnType::Integer),
+    column("co2_kg", ColumnType::Float),
+    column("circuity", ColumnType::Float),
+    column("is_direct", ColumnType::Boolean),
+    column("is_lcc", ColumnType::Boolean),
+    column("is_codeshare", ColumnType::Boolean),
+    column("is_interline", ColumnType::Boolean),
+    column("is_duplicate", ColumnType::Boolean),
+    column("price_rank", ColumnType::Integer),
+    column("itinerary_fingerprint", ColumnType::String),
+];
+
+impl Row for RecoRow {
+    const TABLE: &'static str = "db_recos";
+
+    fn schema() -> &'static [Column] {
+        RECO_SCHEMA
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            self.search_id.clone().into(),
+            self.reco_index.into(),
+            self.nb_of_flights.into(),
+            self.price_eur.into(),
+            self.taxes_eur.into(),
+            self.fees_eur.into(),
+            self.main_marketing_airline.clone().into(),
+            self.main_operating_airline.clone().into(),
+            self.main_airline_alliance.clone().into(),
+            self.main_cabin.clone().into(),
+            self.main_cabin_class.clone().into(),
+            self.trip_type.clone().into(),
+            self.flown_distance.into(),
+            self.co2_kg.into(),
+            self.circuity.into(),
+            self.is_direct.into(),
+            self.is_lcc.into(),
+            self.is_codeshare.into(),
+            self.is_interline.into(),
+            self.is_duplicate.into(),
+            self.price_rank.into(),
+            self.itinerary_fingerprint.clone().into(),
+        ]
+    }
+}
+
+pub struct FlightRow {
+    pub search_id: String,
+    pub reco_index: u64,
+    pub flight_index: u64,
+    pub dep_airport: String,
+    pub arr_airport: String,
+    pub dep_city: String,
+    pub arr_city: String,
+    pub dep_datetime: Option<NaiveDateTime>,
+    pub arr_datetime: Option<NaiveDateTime>,
+    pub marketing_airline: String,
+    pub operating_airline: String,
+    pub flight_nb: String,
+    pub cabin: String,
+    pub distance: u64,
+    pub co2_kg: f64,
+    pub is_rail: bool,
+    pub is_bus: bool,
+}
+
+const FLIGHT_SCHEMA: &[Column] = &[
+    column("search_id", ColumnType::String),
+    column("reco_index", ColumnType::Integer),
+    column("flight_index", ColumnType::Integer),
+    column("dep_airport", ColumnType::Code),
+    column("arr_airport", ColumnType::Code),
+    column("dep_city", ColumnType::Code),
+    column("arr_city", ColumnType::Code),
+    nullable("dep_datetime", ColumnType::DateTime),
+    nullable("arr_datetime", ColumnType::DateTime),
+    column("marketing_airline", ColumnType::Code),
+    column("operating_airline", ColumnType::Code),
+    column("flight_nb", ColumnType::String),
+    column("cabin", ColumnType::Code),
+    column("distance", ColumnType::Integer),
+    column("co2_kg", ColumnType::Float),
+    column("is_rail", ColumnType::Boolean),
+    column("is_bus", ColumnType::Boolean),
+];
+
+impl Row for FlightRow {
+    const TABLE: &'static str = "db_flights";
+
+    fn schema() -> &'static [Column] {
+        FLIGHT_SCHEMA
+    }
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            self.search_id.clone().into(),
+            self.reco_index.into(),
+            self.flight_index.into(),
+            self.dep_airport.clone().into(),
+            self.arr_airport.clone().into(),
+            self.dep_city.clone().into(),
+            self.arr_city.clone().into(),
+            self.dep_datetime.into(),
+            self.arr_datetime.into(),
+            self.marketing_airline.clone().into(),
+            self.operating_airline.clone().into(),
+            self.flight_nb.clone().into(),
+            self.cabin.clone().into(),
+            self.distance.into(),
+            self.co2_kg.into(),
+            self.is_rail.into(),
+            self.is_bus.into(),
+        ]
+    }
+}
+
+/// An enriched search, split into one row per search, reco and flight.
+pub struct FlatSearch {
+    pub search: SearchRow,
+    pub recos: Vec<RecoRow>,
+    pub flights: Vec<FlightRow>,
+}
+
+/// Flattens a search and its enrichment. Recos and flights point to their search with `search_id`,
+/// and flights to their reco with `reco_index`.
+pub fn flatten(search: &Search, enriched_search: &EnrichedSearch) -> FlatSearch {
+    let search_row = SearchRow {
+        search_id: search.search_id.to_string(),
+        search_country: search.search_country.to_string(),
+        search_date: search.search_date,
+        request_dep_date: search.request_dep_date,
+        request_return_date: search.request_return_date,
+        advance_purchase: enriched_search.advance_purchase,
+        stay_duration: enriched_search.stay_duration,
+        trip_type: code(&enriched_search.trip_type),
+        ond: format!("{}-{}", search.origin_city, search.destination_city),
+        origin_city: search.origin_city.to_string(),
+        destination_city: search.destination_city.to_string(),
+        origin_country: enriched_search.origin_country.clone(),
+        destination_country: enriched_search.destination_country.clone(),
+        geo: enriched_search.geo.as_ref().map(code),
+        ond_distance: enriched_search.ond_distance,
+        currency: code(&search.currency),
+        nb_of_recos: enriched_search.recos.len() as u64,
+        min_price_eur: enriched_search.min_price_eur.map(round_money),
+        max_price_eur: enriched_search.max_price_eur.map(round_money),
+        median_price_eur: enriched_search.median_price_eur.map(round_money),
+        cheapest_direct_price_eur: enriched_search.cheapest_direct_price_eur.map(round_money),
+        nb_of_airlines: enriched_search.nb_of_airlines,
+    };
+
+    let mut recos = vec![];
+    let mut flights = vec![];
+    for (reco_index, reco) in enriched_search.recos.iter().enumerate() {
+        recos.push(RecoRow {
+            search_id: search.search_id.to_string(),
+            reco_index: reco_index as u64,
+            nb_of_flights: reco.flights.len() as u64,
+            price_eur: round_money(reco.price_eur),
+            taxes_eur: round_money(reco.taxes_eur),
+            fees_eur: round_money(reco.fees_eur),
+            main_marketing_airline: reco.main_marketing_airline.clone(),
+            main_operating_airline: reco.main_operating_airline.clone(),
+            main_airline_alliance: reco.main_airline_alliance.as_ref().map(code),
+            main_cabin: reco.main_cabin.clone(),
+            main_cabin_class: reco.main_cabin_class.as_ref().map(code),
+            trip_type: code(&reco.trip_type),
+            flown_distance: reco.flown_distance,
+            co2_kg: reco.co2_kg,
+            circuity: reco.circuity,
+            is_direct: reco.is_direct,
+            is_lcc: reco.is_lcc,
+            is_codeshare: reco.is_codeshare,
+            is_interline: reco.is_interline,
+            is_duplicate: reco.is_duplicate,
+            price_rank: reco.price_rank,
+            itinerary_fingerprint: reco.itinerary_fingerprint.clone(),
+        });
+        for (flight_index, flight) in reco.flights.iter().enumerate() {
+            flights.push(FlightRow {
+                search_id: search.search_id.to_string(),
+                reco_index: reco_index as u64,
+                flight_index: flight_index as u64,
+                dep_airport: flight.dep_airport.clone(),
+                arr_airport: flight.arr_airport.clone(),
+                dep_city: flight.dep_city.clone(),
+                arr_city: flight.arr_city.clone(),
+                dep_datetime: flight.dep_datetime,
+                arr_datetime: flight.arr_datetime,
+                marketing_airline: flight.marketing_airline.clone(),
+                operating_airline: flight.operating_airline.clone(),
+                flight_nb: flight.flight_nb.clone(),
+                cabin: flight.cabin.clone(),
+                distance: flight.distance,
+                co2_kg: flight.co2_kg,
+                is_rail: flight.is_rail,
+                is_bus: flight.is_bus,
+            });
+        }
+    }
+
+    FlatSearch {
+        search: search_row,
+        recos,
+        flights,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{enrich_to_rows, sample_search};
+
+    #[test]
+    fn test_flatten_rows() {
+        let mut search = sample_search();
+        // the longest flight of the first reco, booked in a business class
+        search["recos"][0]["flights"][1]["cabin"] = "J".into();
+        let rows = enrich_to_rows(search);
+
+        assert_eq!(rows.recos.len(), 3);
+        assert_eq!(rows.flights.len(), 7);
+        assert_eq!(rows.flights[1].reco_index, 0);
+        assert_eq!(rows.flights[1].flight_nb, "1697");
+        assert_eq!(rows.flights[1].operating_airline, "KL");
+        assert_eq!(rows.recos[0].main_cabin, "J");
+        assert_eq!(rows.recos[0].main_cabin_class.as_deref(), Some("C"));
+        assert_eq!(SearchRow::schema().len(), rows.search.values().len());
+        assert_eq!(RecoRow::schema().len(), rows.recos[0].values().len());
+
+        let searches = ColumnSelection::<SearchRow>::of(SearchRow::DB_SEARCHES_COLUMNS).unwrap();
+        let search = searches.to_json(&rows.search);
+        assert_eq!(search.len(), 8);
+        assert_eq!(search["search_id"], "LRX-51980-1637149713-8763");
+        assert_eq!(search["search_date"], "2021-11-17");
+        assert_eq!(search["stay_duration"], 2);
+        assert_eq!(search["trip_type"], "RT");
+        assert_eq!(search["OnD"], "PAR-LIS");
+
+        let recos =
+            ColumnSelection::<RecoRow>::of(&["price_EUR", "main_marketing_airline"]).unwrap();
+        assert_eq!(
+            recos.select(&rows.recos[0]),
+            vec![Value::Float(578.72), Value::String("KL".to_string())]
+        );
+        assert!(ColumnSelection::<RecoRow>::of(&["price"]).is_err());
+    }
+}
@@ -1,11 +1,32 @@
+use std::sync::OnceLock;
+
+use search::duplicates::{find_duplicates, itinerary_fingerprint};
 use search::enriched_search::{EnrichSearchError, EnrichedSearch};
 use search::Search;
+use serde::{Deserialize, Deserializer};
 use serde_json_helpers::merge_jsons;
+use serde_json_helpers::passthrough::{Merged, RawObject};
 
+pub mod airlines;
+pub mod batch;
+pub mod benchmark;
+pub mod cabin;
+pub mod columnar;
 pub mod currency_exchange;
+pub mod dump;
+pub mod emissions;
+#[cfg(test)]
+mod fixtures;
+pub mod flatten;
+pub mod metrics;
+pub mod monitoring;
 pub mod neobase;
-mod search;
+pub mod reference_data;
+pub mod search;
 mod serde_json_helpers;
+pub mod server;
+pub mod sinks;
+pub mod sketch;
 
 #[derive(Debug, thiserror::Error)]
 pub enum EnrichJsonError {
@@ -17,29 +38,507 @@ pub enum EnrichJsonError {
     FailedToSerializeEnrichedSearch(#[source] serde_json::Error),
 }
 
-/// Enriches a search with additional data, from Neobase, currency exchange rates and fields from the search itself.
+/// Options of `enrich_json_with_options`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EnrichOptions {
+    /// Drops the recos flying the same itinerary as a cheaper one, instead of flagging them.
+    pub keep_only_cheapest_per_itinerary: bool,
+}
+
+/// Enriches a search with additional data, from Neobase, currency exchange rates, CO2 estimates, airline reference data and fields from the search itself.
 /// Returns a new JSON object with the enriched data merged with the input JSON.
 /// Fields in the input JSON will be overridden by the enriched data, but fields not present in the enriched data will be kept.
+/// CO2 estimates and airlines come from the reference files of the crate.
 pub fn enrich_json(
     input_json: serde_json::Value,
     neobase_locations: &neobase::Locations,
     exchange_rates: &currency_exchange::ExchangeRates,
 ) -> Result<serde_json::Value, EnrichJsonError> {
-    // Serialize
-    let search: Search =
-        serde_json::from_value(input_json.clone()).map_err(EnrichJsonError::FailedToParseSearch)?;
+    let (emissions_model, airlines) = default_models();
+    enrich_json_with_models(
+        input_json,
+        neobase_locations,
+        exchange_rates,
+        emissions_model,
+        airlines,
+    )
+}
+
+/// The emissions model and airlines of the crate, loaded on the first call to `enrich_json`.
+fn default_models() -> &'static (emissions::EmissionsModel, airlines::Airlines) {
+    static DEFAULT_MODELS: OnceLock<(emissions::EmissionsModel, airlines::Airlines)> =
+        OnceLock::new();
+    DEFAULT_MODELS.get_or_init(|| (emissions::EmissionsModel::new(), airlines::Airlines::new()))
+}
 
-    // Enrich
-    let enriched_search = EnrichedSearch::enrich_from(&search, neobase_locations, exchange_rates)
-        .map_err(EnrichJsonError::FailedToEnrichSearch)?;
+/// Same as `enrich_json`, with the given emissions model and airlines.
+pub fn enrich_json_with_models(
+    input_json: serde_json::Value,
+    neobase_locations: &neobase::Locations,
+    exchange_rates: &currency_exchange::ExchangeRates,
+    emissions_model: &emissions::EmissionsModel,
+    airlines: &airlines::Airlines,
+) -> Result<serde_json::Value, EnrichJsonError> {
+    enrich_json_with_options(
+        input_json,
+        neobase_locations,
+        exchange_rates,
+        emissions_model,
+        airlines,
+        EnrichOptions::default(),
+    )
+}
+
+/// Same as `enrich_json_with_models`, with options.
+pub fn enrich_json_with_options(
+    input_json: serde_json::Value,
+    neobase_locations: &neobase::Locations,
+    exchange_rates: &currency_exchange::ExchangeRates,
+    emissions_model: &emissions::EmissionsModel,
+    airlines: &airlines::Airlines,
+    options: EnrichOptions,
+) -> Result<serde_json::Value, EnrichJsonError> {
+    // the search borrows its strings from the input json, which is merged with the enrichment
+    let (_, enriched_search, duplicates) = enrich_search(
+        &input_json,
+        neobase_locations,
+        exchange_rates,
+        emissions_model,
+        airlines,
+        options,
+    )?;
 
     // back to json
     let enriched_search_json = serde_json::to_value(enriched_search)
         .map_err(EnrichJsonError::FailedToSerializeEnrichedSearch)?;
 
-    // merge jsons
+    // merge jsons, reco by reco: the dropped recos are dropped from the input json too
     let mut out_json = input_json;
+    if let (Some(duplicates), Some(recos)) = (duplicates, out_json["recos"].as_array_mut()) {
+        let mut is_duplicate = duplicates.into_iter();
+        recos.retain(|_| !is_duplicate.next().unwrap());
+    }
     merge_jsons(&mut out_json, enriched_search_json);
 
     Ok(out_json)
 }
+
+/// Enriches a search like `enrich_json_with_options`, and flattens it into database-ready rows
+/// instead of JSON.
+pub fn enrich_json_to_rows(
+    input_json: serde_json::Value,
+    neobase_locations: &neobase::Locations,
+    exchange_rates: &currency_exchange::ExchangeRates,
+    emissions_model: &emissions::EmissionsModel,
+    airlines: &airlines::Airlines,
+    options: EnrichOptions,
+) -> Result<flatten::FlatSearch, EnrichJsonError> {
+    let (search, enriched_search, _) = enrich_search(
+        &input_json,
+        neobase_locations,
+        exchange_rates,
+        emissions_model,
+        airlines,
+        options,
+    )?;
+    Ok(flatten::flatten(&search, &enriched_search))
+}
+
+/// Parses and enriches a search like `enrich_json_with_options`, and returns it typed, with its
+/// enrichment. The strings of the search are moved out of the input json.
+pub fn enrich_json_to_search(
+    input_json: serde_json::Value,
+    neobase_locations: &neobase::Locations,
+    exchange_rates: &currency_exchange::ExchangeRates,
+    emissions_model: &emissions::EmissionsModel,
+    airlines: &airlines::Airlines,
+    options: EnrichOptions,
+) -> Result<(Search<'static>, EnrichedSearch), EnrichJsonError> {
+    let (search, enriched_search, _) = enrich_search(
+        input_json,
+        neobase_locations,
+        exchange_rates,
+        emissions_model,
+        airlines,
+        options,
+    )?;
+    Ok((search, enriched_search))
+}
+
+/// Same as `enrich_json_with_options`, from and to JSON text, without building a
+/// `serde_json::Value`: the input fields are borrowed from the text and written back verbatim,
+/// the strings of the search are borrowed from it too, and the enrichment is serialized
+/// directly. Keys are ordered differently.
+pub fn enrich_json_str(
+    input: &str,
+    neobase_locations: &neobase::Locations,
+    exchange_rates: &currency_exchange::ExchangeRates,
+    emissions_model: &emissions::EmissionsModel,
+    airlines: &airlines::Airlines,
+    options: EnrichOptions,
+) -> Result<String, EnrichJsonError> {
+    let output = enrich_json_slice(
+        input.as_bytes(),
+        neobase_locations,
+        exchange_rates,
+        emissions_model,
+        airlines,
+        options,
+    )?;
+    Ok(String::from_utf8(output).expect("serde_json writes UTF-8"))
+}
+
+/// Same as `enrich_json_str`, from and to UTF-8 bytes.
+pub fn enrich_json_slice(
+    input: &[u8],
+    neobase_locations: &neobase::Locations,
+    exchange_rates: &currency_exchange::ExchangeRates,
+    emissions_model: &emissions::EmissionsModel,
+    airlines: &airlines::Airlines,
+    options: EnrichOptions,
+) -> Result<Vec<u8>, EnrichJsonError> {
+    let raw_search = RawObject::from_slice::<EnrichedSearch>(input)
+        .map_err(EnrichJsonError::FailedToParseSearch)?;
+    enrich_raw_search(
+        raw_search,
+        neobase_locations,
+        exchange_rates,
+        emissions_model,
+        airlines,
+        options,
+    )
+}
+
+/// Same as `enrich_json_slice`, from the search it read.
+pub(crate) fn enrich_raw_search(
+    mut raw_search: RawObject,
+    neobase_locations: &neobase::Locations,
+    exchange_rates: &currency_exchange::ExchangeRates,
+    emissions_model: &emissions::EmissionsModel,
+    airlines: &airlines::Airlines,
+    options: EnrichOptions,
+) -> Result<Vec<u8>, EnrichJsonError> {
+    let (_, enriched_search, duplicates) = enrich_search(
+        &raw_search,
+        neobase_locations,
+        exchange_rates,
+        emissions_model,
+        airlines,
+        options,
+    )?;
+
+    // the children are merged one by one with the recos
+    if let Some(duplicates) = duplicates {
+        let mut is_duplicate = duplicates.into_iter();
+        raw_search
+            .children
+            .retain(|_| !is_duplicate.next().unwrap());
+    }
+
+    serde_json::to_vec(&Merged {
+        raw_object: &raw_search,
+        enriched: &enriched_search,
+    })
+    .map_err(EnrichJsonError::FailedToSerializeEnrichedSearch)
+}
+
+/// Drops the recos flying the same itinerary as a cheaper one, and returns which ones were.
+fn drop_duplicate_recos(search: &mut Search) -> Vec<bool> {
+    let fingerprints: Vec<String> = search.recos.iter().map(itinerary_fingerprint).collect();
+    let duplicates = find_duplicates(
+        &fingerprints
+            .iter()
+            .zip(&search.recos)
+            .map(|(fingerprint, reco)| (fingerprint.as_str(), reco.price))
+            .collect::<Vec<_>>(),
+    );
+    let mut is_duplicate = duplicates.iter();
+    search.recos.retain(|_| !is_duplicate.next().unwrap());
+    duplicates
+}
+
+/// Parses and enriches a search, borrowing its strings from the input when it can. Also returns
+/// which recos were dropped by the options, if any.
+fn enrich_search<'de>(
+    input: impl Deserializer<'de, Error = serde_json::Error>,
+    neobase_locations: &neobase::Locations,
+    exchange_rates: &currency_exchange::ExchangeRates,
+    emissions_model: &emissions::EmissionsModel,
+    airlines: &airlines::Airlines,
+    options: EnrichOptions,
+) -> Result<(Search<'de>, EnrichedSearch, Option<Vec<bool>>), EnrichJsonError> {
+    let mut search = Search::deserialize(input).map_err(EnrichJsonError::FailedToParseSearch)?;
+
+    let duplicates = options
+        .keep_only_cheapest_per_itinerary
+        .then(|| drop_duplicate_recos(&mut search));
+
+    let enriched_search = EnrichedSearch::enrich_from(
+        &search,
+        neobase_locations,
+        exchange_rates,
+        emissions_model,
+        airlines,
+    )
+    .map_err(EnrichJsonError::FailedToEnrichSearch)?;
+
+    Ok((search, enriched_search, duplicates))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+    use fixtures::{sample_search, NEOBASE_SAMPLE};
+
+    /// The sample search, with the direct TP reco flown again at a higher price after it.
+    fn duplicate_recos_search() -> serde_json::Value {
+        let mut search = sample_search();
+        let mut duplicate = search["recos"][1].clone();
+        duplicate["price"] = "260.00".into();
+        search["recos"].as_array_mut().unwrap().insert(2, duplicate);
+        search
+    }
+
+    fn enrich_with_options(search: serde_json::Value, options: EnrichOptions) -> serde_json::Value {
+        let neobase_locations = neobase::Locations::from_reader(NEOBASE_SAMPLE.as_bytes());
+        let exchange_rates = currency_exchange::ExchangeRates::new();
+        let emissions_model = emissions::EmissionsModel::new();
+        let airlines = airlines::Airlines::new();
+        enrich_json_with_options(
+            search,
+            &neobase_locations,
+            &exchange_rates,
+            &emissions_model,
+            &airlines,
+            options,
+        )
+        .unwrap()
+    }
+
+    fn enrich_sample() -> serde_json::Value {
+        enrich_with_options(sample_search(), EnrichOptions::default())
+    }
+
+    #[test]
+    fn test_enrich_json_with_default_models() {
+        let enriched = enrich_json(
+            sample_search(),
+            &neobase::Locations::from_reader(NEOBASE_SAMPLE.as_bytes()),
+            &currency_exchange::ExchangeRates::new(),
+        )
+        .unwrap();
+        assert_eq!(enriched, enrich_sample());
+    }
+
+    #[test]
+    fn test_enrich_keeps_input_fields() {
+        let enriched = enrich_sample();
+
+        assert_eq!(enriched["search_id"], "LRX-51980-1637149713-8763");
+        assert_eq!(enriched["recos"][0]["flights"][0]["flight_nb"], "1246");
+        assert_eq!(enriched["advance_purchase"], 30);
+        assert_eq!(enriched["trip_type"], "RT");
+    }
+
+    #[test]
+    fn test_search_id_is_optional() {
+        let mut search = sample_search();
+        search.as_object_mut().unwrap().remove("search_id");
+
+        let enriched = enrich_with_options(search, EnrichOptions::default());
+        assert_eq!(enriched["recos"], enrich_sample()["recos"]);
+    }
+
+    #[test]
+    fn test_circuity() {
+        let enriched = enrich_sample();
+        let via_ams = &enriched["recos"][0];
+        let direct = &enriched["recos"][1];
+
+        assert_eq!(via_ams["has_backtracking"], true);
+        assert!(via_ams["circuity"].as_f64().unwrap() > 1.2);
+        assert_eq!(via_ams["bound_circuities"].as_array().unwrap().len(), 2);
+        assert!(via_ams["detour_km"].as_i64().unwrap() > 300);
+
+        assert_eq!(direct["has_backtracking"], false);
+        assert!((direct["circuity"].as_f64().unwrap() - 1.0).abs() < 0.05);
+
+        // the inbound leaves from OPO: it is compared with OPO-PAR, not LIS-PAR
+        let open_jaw = &enriched["recos"][2];
+        assert!((open_jaw["bound_circuities"][1].as_f64().unwrap() - 1.0).abs() < 0.05);
+        assert!(open_jaw["detour_km"].as_i64().unwrap().abs() < 50);
+    }
+
+    #[test]
+    fn test_reco_trip_type() {
+        let enriched = enrich_sample();
+
+        assert_eq!(enriched["recos"][0]["trip_type"], "RT");
+        assert_eq!(enriched["recos"][1]["trip_type"], "RT");
+        assert_eq!(enriched["recos"][2]["trip_type"], "OJD");
+    }
+
+    #[test]
+    fn test_search_trip_type() {
+        // the recos fly a round trip and an open jaw
+        assert_eq!(enrich_sample()["trip_type"], "RT");
+
+        let mut search = sample_search();
+        search["recos"].as_array_mut().unwrap().truncate(2);
+        search["request_return_date"] = "".into();
+        assert_eq!(
+            enrich_with_options(search, EnrichOptions::default())["trip_type"],
+            "RT"
+        );
+
+        let mut search = sample_search();
+        search["recos"].as_array_mut().unwrap().drain(..2);
+        assert_eq!(
+            enrich_with_options(search, EnrichOptions::default())["trip_type"],
+            "OJD"
+        );
+    }
+
+    #[test]
+    fn test_airline_flags() {
+        let enriched = enrich_sample();
+        let kl_tp = &enriched["recos"][0];
+        let tp = &enriched["recos"][1];
+
+        assert_eq!(kl_tp["main_airline_alliance"], "SkyTeam");
+        assert_eq!(kl_tp["is_interline"], true);
+        assert_eq!(kl_tp["is_codeshare"], false);
+        assert_eq!(kl_tp["is_lcc"], false);
+        assert_eq!(kl_tp["flights"][1]["operating_airline"], "KL");
+        assert_eq!(tp["is_interline"], false);
+    }
+
+    #[test]
+    fn test_cabins() {
+        let enriched = enrich_sample();
+        let mixed = &enriched["recos"][0];
+        let economy = &enriched["recos"][1];
+
+        assert_eq!(mixed["flights"][2]["cabin"], "J");
+        assert_eq!(mixed["flights"][2]["cabin_class"], "C");
+        assert_eq!(mixed["highest_cabin"], "C");
+        assert_eq!(mixed["main_cabin_class"], "M");
+        assert_eq!(mixed["lowest_cabin"], "M");
+        assert_eq!(mixed["mixed_cabin"], true);
+        assert_eq!(economy["highest_cabin"], "M");
+        assert_eq!(economy["mixed_cabin"], false);
+    }
+
+    #[test]
+    fn test_search_price_statistics() {
+        let enriched = enrich_sample();
+
+        assert_eq!(enriched["min_price_EUR"], 199.99);
+        assert_eq!(enriched["max_price_EUR"], 578.72);
+        assert_eq!(enriched["median_price_EUR"], 250.5);
+        assert_eq!(enriched["cheapest_reco_index"], 2);
+        assert_eq!(enriched["cheapest_direct_price_EUR"], 199.99);
+        assert_eq!(enriched["nb_of_airlines"], 2);
+        assert_eq!(enriched["recos"][0]["price_rank"], 3);
+        assert_eq!(enriched["recos"][1]["price_rank"], 2);
+        assert_eq!(enriched["recos"][2]["price_rank"], 1);
+    }
+
+    #[test]
+    fn test_duplicate_recos() {
+        let enriched = enrich_with_options(duplicate_recos_search(), EnrichOptions::default());
+        let recos = enriched["recos"].as_array().unwrap();
+
+        assert_eq!(
+            recos[1]["itinerary_fingerprint"],
+            recos[2]["itinerary_fingerprint"]
+        );
+        assert_ne!(
+            recos[0]["itinerary_fingerprint"],
+            recos[1]["itinerary_fingerprint"]
+        );
+        assert_eq!(recos[1]["is_duplicate"], false);
+        assert_eq!(recos[2]["is_duplicate"], true);
+        assert_eq!(enriched["nb_of_duplicate_recos"], 1);
+
+        let deduplicated = enrich_with_options(
+            duplicate_recos_search(),
+            EnrichOptions {
+                keep_only_cheapest_per_itinerary: true,
+            },
+        );
+        let recos = deduplicated["recos"].as_array().unwrap();
+        assert_eq!(recos.len(), 3);
+        assert_eq!(recos[1]["price"], 250.5);
+        assert_eq!(recos[2]["trip_type"], "OJD");
+        assert_eq!(deduplicated["nb_of_duplicate_recos"], 0);
+    }
+
+    #[test]
+    fn test_enrich_json_str() {
+        let neobase_locations = neobase::Locations::from_reader(NEOBASE_SAMPLE.as_bytes());
+        let mut search = sample_search();
+        search["source\tsystem"] = serde_json::json!({"name": "pricer", "version": [1, 2]});
+        search["recos"][0]["flights"][1]["aircraft"] = "E90".into();
+        search["recos"][1]["nb_of_flights"] = "overridden by nothing".into();
+        let input = serde_json::to_string(&search).unwrap();
+
+        for options in [
+            EnrichOptions::default(),
+            EnrichOptions {
+                keep_only_cheapest_per_itinerary: true,
+            },
+        ] {
+            let enrich_str = |input: &str| {
+                enrich_json_str(
+                    input,
+                    &neobase_locations,
+                    &currency_exchange::ExchangeRates::new(),
+                    &emissions::EmissionsModel::new(),
+                    &airlines::Airlines::new(),
+                    options,
+                )
+            };
+            let output: serde_json::Value =
+                serde_json::from_str(&enrich_str(&input).unwrap()).unwrap();
+            let expected = enrich_json_with_options(
+                search.clone(),
+                &neobase_locations,
+                &currency_exchange::ExchangeRates::new(),
+                &emissions::EmissionsModel::new(),
+                &airlines::Airlines::new(),
+                options,
+            )
+            .unwrap();
+            assert_eq!(output, expected);
+            assert_eq!(output["source\tsystem"]["version"][1], 2);
+            assert_eq!(output["recos"][0]["flights"][1]["aircraft"], "E90");
+            assert_eq!(output["recos"][0]["flights"][1]["operating_airline"], "KL");
+
+            assert!(matches!(
+                enrich_str(&input[1..]),
+                Err(EnrichJsonError::FailedToParseSearch(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_search_borrows_from_the_input_text() {
+        let mut search = sample_search();
+        search["recos"][0]["flights"][0]["flight_nb"] = "12\t46".into();
+        let input = serde_json::to_vec(&search).unwrap();
+        let raw_search = RawObject::from_slice::<EnrichedSearch>(&input).unwrap();
+        let search = Search::deserialize(&raw_search).unwrap();
+
+        assert!(matches!(search.origin_city, Cow::Borrowed("PAR")));
+        let flight = &search.recos[0].flights[0];
+        assert!(matches!(
+            flight.operating_airline,
+            Some(Cow::Borrowed("KL"))
+        ));
+        // escaped strings cannot be borrowed
+        assert!(matches!(&flight.flight_nb, Cow::Owned(flight_nb) if flight_nb == "12\t46"));
+    }
+}
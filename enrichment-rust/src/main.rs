@@ -1,13 +1,389 @@
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter};
+use std::sync::Arc;
+use std::time::Duration;
 
-use enrichment_rust_lib::currency_exchange;
-use enrichment_rust_lib::enrich_json;
-use enrichment_rust_lib::neobase;
+use enrichment_rust_lib::batch::{enrich_batch_with, BatchOptions, BatchReport};
+use enrichment_rust_lib::columnar::{FlatSearchParquetWriter, ParquetOptions};
+use enrichment_rust_lib::dump::{read_ndjson, CsvDumpReader, DumpError};
+use enrichment_rust_lib::flatten::FlatSearch;
+use enrichment_rust_lib::metrics::EnrichmentMetrics;
+#[cfg(feature = "kafka")]
+use enrichment_rust_lib::monitoring::kafka::KafkaAlertSink;
+use enrichment_rust_lib::monitoring::{AlertSink, MonitorOptions, NdjsonAlertSink, PriceMonitor};
+use enrichment_rust_lib::reference_data::reload::{ReloadTriggers, Reloader};
+use enrichment_rust_lib::reference_data::{ReferenceData, ReferenceDataHandle, ReferenceDataPaths};
+use enrichment_rust_lib::search::{enriched_search::EnrichedSearch, Search};
+use enrichment_rust_lib::server::{serve_metrics, EnrichmentServer};
+use enrichment_rust_lib::sinks::postgres::PostgresSink;
+use enrichment_rust_lib::sinks::sqlite::SqliteSink;
+use enrichment_rust_lib::{
+    enrich_json_to_rows, enrich_json_to_search, enrich_json_with_models, EnrichJsonError,
+    EnrichOptions,
+};
+
+const USAGE: &str = "\
+Usage:
+    enrichment-rust
+        Enriches sample.json into out.json
+    enrichment-rust <output> [--input <file>] [--batch-size <n>] [--threads <n>] [--metrics <address>]
+        Enriches searches into database-ready rows. They are read from stdin, one JSON per line,
+        or from a file: `^`-separated dump if its name ends with .csv, one JSON per line otherwise.
+        Enriches on n threads, one per CPU by default, and reports the throughput on stderr.
+        Serves Prometheus metrics on http://<address>/metrics meanwhile with --metrics.
+    enrichment-rust --serve <address> [--threads <n>]
+        Serves the enrichment over HTTP on an address like 0.0.0.0:8080, with n threads (one per
+        CPU by default): POST /enrich, GET /locations/{code}, GET /rates/{currency}, GET /health,
+        GET /ready and GET /metrics.
+
+Reference data, reloaded without restart by --serve, and by the outputs with --reload-every, when
+its files change, on SIGHUP or on a schedule:
+    --neobase <file>                NeoBase file (src/neobase/data.csv)
+    --rates <file>                  ECB rates file (src/currency_exchange/eurofxref.csv)
+    --reload-every <seconds>        Reloads on a schedule too
+
+Outputs:
+    --parquet <directory>           Parquet files, --batch-size rows buffered per search date
+    --postgres <connection string>  Postgres tables, --batch-size searches per transaction
+    --sqlite <database file>        SQLite tables, --batch-size searches per transaction
+    --alerts <file>                 Price alerts, one JSON per line, `-` for stdout
+    --kafka-alerts <brokers>        Price alerts to the --topic Kafka topic (kafka feature only)
+
+Price alerts, when the cheapest price of an OnD, airline and cabin deviates from its last prices:
+    --window <n>                    Number of last prices per OnD, airline and cabin (100)
+    --min-observations <n>          Prices needed before alerting (10)
+    --max-stddevs <n>               Alerts beyond n standard deviations (3), `none` to disable
+    --max-deviation <percent>       Alerts beyond this percentage (none)
+    --topic <topic>                 Kafka topic of --kafka-alerts (price_alerts, kafka feature only)";
+
+/// Default number of searches written to a database per transaction.
+const DATABASE_BATCH_SIZE: usize = 1000;
+
+#[cfg(feature = "kafka")]
+const ALERTS_TOPIC: &str = "price_alerts";
+
+enum Output {
+    Parquet(String),
+    Postgres(String),
+    Sqlite(String),
+    Alerts(String),
+    #[cfg(feature = "kafka")]
+    KafkaAlerts(String),
+    Serve(String),
+}
+
+struct Args {
+    output: Output,
+    input: Option<String>,
+    batch_size: Option<usize>,
+    monitor_options: MonitorOptions,
+    #[cfg(feature = "kafka")]
+    topic: String,
+    metrics_address: Option<String>,
+    threads: Option<usize>,
+    reference_data_paths: ReferenceDataPaths,
+    reload_every: Option<Duration>,
+}
+
+/// `None` when there are no arguments.
+fn parse_args(args: &[String]) -> Option<Args> {
+    let (output, mut options) = match args {
+        [] => return None,
+        [flag] if flag == "--help" => {
+            println!("{USAGE}");
+            std::process::exit(0);
+        }
+        [flag, value, options @ ..] => {
+            let output = match flag.as_str() {
+                "--parquet" => Output::Parquet(value.clone()),
+                "--postgres" => Output::Postgres(value.clone()),
+                "--sqlite" => Output::Sqlite(value.clone()),
+                "--alerts" => Output::Alerts(value.clone()),
+                // without the kafka feature, the flag is invalid
+                #[cfg(feature = "kafka")]
+                "--kafka-alerts" => Output::KafkaAlerts(value.clone()),
+                "--serve" => Output::Serve(value.clone()),
+                _ => exit_with_usage(),
+            };
+            (output, options)
+        }
+        _ => exit_with_usage(),
+    };
+
+    let mut args = Args {
+        output,
+        input: None,
+        batch_size: None,
+        monitor_options: MonitorOptions::default(),
+        #[cfg(feature = "kafka")]
+        topic: ALERTS_TOPIC.to_string(),
+        metrics_address: None,
+        threads: None,
+        reference_data_paths: ReferenceDataPaths::default(),
+        reload_every: None,
+    };
+    let threshold = |value: &String| match value.as_str() {
+        "none" => None,
+        value => Some(parse(value)),
+    };
+    while let [flag, value, rest @ ..] = options {
+        match flag.as_str() {
+            "--input" => args.input = Some(value.clone()),
+            "--batch-size" => args.batch_size = Some(parse(value)),
+            "--metrics" => args.metrics_address = Some(value.clone()),
+            "--window" => args.monitor_options.window_size = parse(value),
+            "--min-observations" => args.monitor_options.min_observations = parse(value),
+            "--max-stddevs" => args.monitor_options.max_stddevs = threshold(value),
+            "--max-deviation" => args.monitor_options.max_deviation_percent = threshold(value),
+            #[cfg(feature = "kafka")]
+            "--topic" => args.topic = value.clone(),
+            "--threads" => args.threads = Some(parse(value)),
+            "--neobase" => args.reference_data_paths.neobase = value.into(),
+            "--rates" => args.reference_data_paths.exchange_rates = value.into(),
+            "--reload-every" => args.reload_every = Some(Duration::from_secs(parse(value))),
+            _ => exit_with_usage(),
+        }
+        options = rest;
+    }
+    if !options.is_empty() {
+        exit_with_usage();
+    }
+    Some(args)
+}
+
+/// Parses the value of an option, or exits with the usage.
+fn parse<T: std::str::FromStr>(value: &str) -> T {
+    value.parse().unwrap_or_else(|_| exit_with_usage())
+}
+
+/// Exits like `exit_with_error`, for invalid arguments.
+fn exit_with_usage() -> ! {
+    eprintln!("{USAGE}");
+    std::process::exit(2)
+}
+
+/// Loads the reference data, and reloads it in the background when there are triggers.
+fn load_reference_data(
+    paths: ReferenceDataPaths,
+    triggers: Option<ReloadTriggers>,
+) -> Arc<ReferenceDataHandle> {
+    let reference_data = Arc::new(
+        ReferenceDataHandle::load(paths)
+            .unwrap_or_else(|error| exit_with_error("Failed to load reference data", error)),
+    );
+    if let Some(triggers) = triggers {
+        Reloader::new(reference_data.clone(), triggers)
+            .unwrap_or_else(|error| exit_with_error("Failed to watch reference data", error))
+            .spawn();
+    }
+    reference_data
+}
+
+/// Exits the process, as a panic would only end the thread loading the data of the server, and
+/// would print a backtrace hint for errors that are not bugs.
+fn exit_with_error(message: &str, error: impl std::fmt::Display) -> ! {
+    eprintln!("{message}: {error}");
+    std::process::exit(1)
+}
+
+/// Listens right away, and is ready once the reference data is loaded.
+fn serve(
+    address: &str,
+    threads: Option<usize>,
+    paths: ReferenceDataPaths,
+    triggers: Option<ReloadTriggers>,
+) {
+    let threads = threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |threads| threads.get()));
+    let server = EnrichmentServer::bind(address).expect("Failed to start server");
+    std::thread::scope(|scope| {
+        scope.spawn(|| server.set_reference_data(load_reference_data(paths, triggers)));
+        server.run(threads);
+    });
+}
+
+fn read_searches(
+    input: Option<&str>,
+) -> Box<dyn Iterator<Item = Result<serde_json::Value, DumpError>>> {
+    let reader: Box<dyn BufRead> = match input {
+        Some(path) => Box::new(BufReader::new(File::open(path).unwrap_or_else(|error| {
+            exit_with_error(&format!("Failed to open input file {path}"), error)
+        }))),
+        None => Box::new(io::stdin().lock()),
+    };
+    match input {
+        Some(path) if path.ends_with(".csv") => Box::new(CsvDumpReader::new(reader)),
+        _ => Box::new(read_ndjson(reader)),
+    }
+}
 
 fn main() {
-    // Utils
-    let neobase_locations = neobase::Locations::new();
-    let exchange_rates = currency_exchange::ExchangeRates::new();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(args) = parse_args(&args) {
+        // only long-running modes reload, a batch run enriches with a single version of the data
+        let reload = matches!(args.output, Output::Serve(_)) || args.reload_every.is_some();
+        let reload_triggers = reload.then_some(ReloadTriggers {
+            file_changes: true,
+            sighup: true,
+            every: args.reload_every,
+        });
+        if let Output::Serve(address) = &args.output {
+            serve(
+                address,
+                args.threads,
+                args.reference_data_paths,
+                reload_triggers,
+            );
+            return;
+        }
+        let reference_data = load_reference_data(args.reference_data_paths, reload_triggers);
+        let metrics = Arc::new(EnrichmentMetrics::new());
+        if let Some(address) = &args.metrics_address {
+            serve_metrics(address, metrics.clone()).expect("Failed to serve metrics");
+        }
+        // invalid lines are skipped, a failure to read the input aborts
+        let searches = read_searches(args.input.as_deref()).filter_map(|search| match search {
+            Ok(search) => Some(search),
+            Err(DumpError::Io(error)) => exit_with_error("Failed to read input", error),
+            Err(error) => {
+                metrics.record_read_error();
+                eprintln!("Skipped: {error}");
+                None
+            }
+        });
+        let rows = |search: serde_json::Value| {
+            let search_id = search_id(&search);
+            let result = metrics.observe(
+                search,
+                |search| {
+                    let reference_data = reference_data.snapshot();
+                    enrich_json_to_rows(
+                        search,
+                        &reference_data.locations,
+                        &reference_data.exchange_rates,
+                        &reference_data.emissions_model,
+                        &reference_data.airlines,
+                        EnrichOptions::default(),
+                    )
+                },
+                |flat_search| flat_search.recos.len(),
+            );
+            log_failure(&search_id, result)
+        };
+        let typed = |search: serde_json::Value| {
+            let search_id = search_id(&search);
+            let result = metrics.observe(
+                search,
+                |search| {
+                    let reference_data = reference_data.snapshot();
+                    enrich_json_to_search(
+                        search,
+                        &reference_data.locations,
+                        &reference_data.exchange_rates,
+                        &reference_data.emissions_model,
+                        &reference_data.airlines,
+                        EnrichOptions::default(),
+                    )
+                },
+                |(_, enriched_search)| enriched_search.recos.len(),
+            );
+            log_failure(&search_id, result)
+        };
+        let batch_options = BatchOptions {
+            threads: args.threads,
+            preserve_order: false,
+            ..BatchOptions::default()
+        };
+
+        let report = match args.output {
+            Output::Parquet(directory) => {
+                let mut options = ParquetOptions::default();
+                if let Some(batch_size) = args.batch_size {
+                    options.batch_size = batch_size;
+                }
+                let mut writer = FlatSearchParquetWriter::new(directory, options);
+                let report = enrich_batch_with(searches, rows, batch_options, |flat_search| {
+                    if let Ok(flat_search) = flat_search {
+                        writer.write(flat_search).expect("Failed to write rows")
+                    }
+                })
+                .expect("Failed to start enrichment threads");
+                writer.close().expect("Failed to close Parquet files");
+                report
+            }
+            Output::Postgres(params) => {
+                let mut sink =
+                    PostgresSink::connect(&params).expect("Failed to connect to Postgres");
+                let mut batches = Batches::new(args.batch_size, |batch| {
+                    sink.write(batch).expect("Failed to write to Postgres")
+                });
+                // the last of the searches replayed with the same ID is the one stored
+                let batch_options = BatchOptions {
+                    preserve_order: true,
+                    ..batch_options
+                };
+                let report = enrich_batch_with(searches, rows, batch_options, |flat_search| {
+                    if let Some(flat_search) = flat_search.ok().filter(has_search_id) {
+                        batches.push(flat_search)
+                    }
+                })
+                .expect("Failed to start enrichment threads");
+                batches.finish();
+                report
+            }
+            Output::Sqlite(path) => {
+                let mut sink = SqliteSink::open(path).expect("Failed to open SQLite database");
+                let mut batches = Batches::new(args.batch_size, |batch| {
+                    sink.write(batch).expect("Failed to write to SQLite")
+                });
+                // the last of the searches replayed with the same ID is the one stored
+                let batch_options = BatchOptions {
+                    preserve_order: true,
+                    ..batch_options
+                };
+                let report = enrich_batch_with(searches, rows, batch_options, |flat_search| {
+                    if let Some(flat_search) = flat_search.ok().filter(has_search_id) {
+                        batches.push(flat_search)
+                    }
+                })
+                .expect("Failed to start enrichment threads");
+                batches.finish();
+                report
+            }
+            Output::Alerts(path) => {
+                let writer: Box<dyn io::Write> = match path.as_str() {
+                    "-" => Box::new(io::stdout().lock()),
+                    path => Box::new(BufWriter::new(
+                        File::create(path).expect("Failed to create alerts file"),
+                    )),
+                };
+                let mut sink = NdjsonAlertSink::new(writer);
+                monitor(
+                    searches,
+                    typed,
+                    batch_options,
+                    &mut sink,
+                    args.monitor_options,
+                )
+            }
+            #[cfg(feature = "kafka")]
+            Output::KafkaAlerts(brokers) => {
+                let mut sink =
+                    KafkaAlertSink::new(&brokers, args.topic).expect("Failed to connect to Kafka");
+                monitor(
+                    searches,
+                    typed,
+                    batch_options,
+                    &mut sink,
+                    args.monitor_options,
+                )
+            }
+            Output::Serve(_) => unreachable!("served above"),
+        };
+        eprintln!("{report}");
+        return;
+    }
+    let reference_data = ReferenceData::load();
 
     // Read sample.json
     let input_json = serde_json::from_str(
@@ -16,8 +392,14 @@ fn main() {
     .expect("Failed to parse sample.json");
 
     // Enrich
-    let output_json = enrich_json(input_json, &neobase_locations, &exchange_rates)
-        .expect("Failed to enrich json");
+    let output_json = enrich_json_with_models(
+        input_json,
+        &reference_data.locations,
+        &reference_data.exchange_rates,
+        &reference_data.emissions_model,
+        &reference_data.airlines,
+    )
+    .expect("Failed to enrich json");
 
     // write to file
     fs::write(
@@ -26,3 +408,85 @@ fn main() {
     )
     .expect("Failed to write out.json");
 }
+
+fn search_id(search: &serde_json::Value) -> String {
+    search["search_id"].as_str().unwrap_or_default().to_string()
+}
+
+/// Logs the searches that could not be enriched, which are skipped.
+fn log_failure<T>(
+    search_id: &str,
+    result: Result<T, EnrichJsonError>,
+) -> Result<T, EnrichJsonError> {
+    if let Err(error) = &result {
+        eprintln!("Skipped search {search_id:?}: {error}");
+    }
+    result
+}
+
+/// The database sinks replace searches by ID, so they reject searches without one: skip them
+/// instead of failing the whole batch.
+fn has_search_id(flat_search: &FlatSearch) -> bool {
+    let has_search_id = !flat_search.search.search_id.is_empty();
+    if !has_search_id {
+        eprintln!("Skipped search without search_id");
+    }
+    has_search_id
+}
+
+/// Sends the price alerts raised by the enriched searches, in the order of the input.
+fn monitor(
+    searches: impl Iterator<Item = serde_json::Value>,
+    enrich: impl Fn(serde_json::Value) -> Result<(Search<'static>, EnrichedSearch), EnrichJsonError>
+        + Sync,
+    batch_options: BatchOptions,
+    sink: &mut impl AlertSink,
+    options: MonitorOptions,
+) -> BatchReport {
+    let mut monitor = PriceMonitor::new(options);
+    let batch_options = BatchOptions {
+        preserve_order: true,
+        ..batch_options
+    };
+    let report = enrich_batch_with(searches, enrich, batch_options, |result| {
+        let Ok((search, enriched_search)) = result else {
+            return;
+        };
+        for alert in monitor.observe(&search, &enriched_search) {
+            sink.send(&alert).expect("Failed to send alert");
+        }
+    })
+    .expect("Failed to start enrichment threads");
+    sink.flush().expect("Failed to flush alerts");
+    report
+}
+
+/// Writes rows `batch_size` searches at a time.
+struct Batches<W: FnMut(&[FlatSearch])> {
+    batch: Vec<FlatSearch>,
+    batch_size: usize,
+    write: W,
+}
+
+impl<W: FnMut(&[FlatSearch])> Batches<W> {
+    fn new(batch_size: Option<usize>, write: W) -> Self {
+        let batch_size = batch_size.unwrap_or(DATABASE_BATCH_SIZE);
+        Batches {
+            batch: Vec::with_capacity(batch_size),
+            batch_size,
+            write,
+        }
+    }
+
+    fn push(&mut self, flat_search: FlatSearch) {
+        self.batch.push(flat_search);
+        if self.batch.len() >= self.batch_size {
+            (self.write)(&self.batch);
+            self.batch.clear();
+        }
+    }
+
+    fn finish(mut self) {
+        (self.write)(&self.batch);
+    }
+}
@@ -0,0 +1,324 @@
+use std::str::FromStr;
+use std::time::Instant;
+
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::{
+    currency_exchange::Currency,
+    search::{
+        enriched_flight::EnrichFlightError, enriched_reco::EnrichRecoError,
+        enriched_search::EnrichSearchError,
+    },
+    serde_json_helpers::passthrough::RawObject,
+    EnrichJsonError,
+};
+
+/// Content type of `EnrichmentMetrics::encode`.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Label of a failed search, after the innermost error variant.
+fn error_label(error: &EnrichJsonError) -> &'static str {
+    match error {
+        EnrichJsonError::FailedToParseSearch(_) => "failed_to_parse_search",
+        EnrichJsonError::FailedToSerializeEnrichedSearch(_) => {
+            "failed_to_serialize_enriched_search"
+        }
+        EnrichJsonError::FailedToEnrichSearch(error) => match error {
+            EnrichSearchError::EnrichReco(EnrichRecoError::NoFlightInReco) => "no_flight_in_reco",
+            EnrichSearchError::EnrichReco(EnrichRecoError::ExchangeRate(_)) => {
+                "missing_exchange_rate"
+            }
+            EnrichSearchError::EnrichReco(EnrichRecoError::EnrichFlight(
+                EnrichFlightError::MissingLocationInDistanceCalculation { .. },
+            ))
+            | EnrichSearchError::MissingLocationInDistanceCalculation { .. } => {
+                "missing_location_in_distance_calculation"
+            }
+            EnrichSearchError::SearchDateAfterRequestDepDate(_) => {
+                "search_date_after_request_dep_date"
+            }
+            EnrichSearchError::RequestDepDateAfterRequestReturnDate(_) => {
+                "request_dep_date_after_request_return_date"
+            }
+            EnrichSearchError::FailedToParsePassengersString(_) => {
+                "failed_to_parse_passengers_string"
+            }
+        },
+    }
+}
+
+/// Prometheus metrics of the enrichment, in their own registry.
+pub struct EnrichmentMetrics {
+    registry: Registry,
+    searches_read: IntCounter,
+    searches_enriched: IntCounter,
+    searches_failed: IntCounterVec,
+    recos_dropped: IntCounterVec,
+    unknown_airports: IntCounter,
+    unknown_currencies: IntCounter,
+    enrichment_duration: Histogram,
+    recos_per_search: Histogram,
+}
+
+impl Default for EnrichmentMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EnrichmentMetrics {
+    pub fn new() -> Self {
+        let metrics = EnrichmentMetrics {
+            registry: Registry::new(),
+            searches_read: IntCounter::new(
+                "enrichment_searches_read_total",
+                "Searches read from the input",
+            )
+            .unwrap(),
+            searches_enriched: IntCounter::new(
+                "enrichment_searches_enriched_total",
+                "Searches enriched",
+            )
+            .unwrap(),
+            searches_failed: IntCounterVec::new(
+                Opts::new(
+                    "enrichment_searches_failed_total",
+                    "Searches that could not be read or enriched, by error",
+                ),
+                &["error"],
+            )
+            .unwrap(),
+            recos_dropped: IntCounterVec::new(
+                Opts::new(
+                    "enrichment_recos_dropped_total",
+                    "Recos missing from the output, as duplicates or in failed searches",
+                ),
+                &["reason"],
+            )
+            .unwrap(),
+            unknown_airports: IntCounter::new(
+                "enrichment_unknown_airports_total",
+                "Searches failed on a location missing from NeoBase",
+            )
+            .unwrap(),
+            unknown_currencies: IntCounter::new(
+                "enrichment_unknown_currencies_total",
+                "Searches failed on an unsupported currency",
+            )
+            .unwrap(),
+            // from 50µs to about 3s
+            enrichment_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "enrichment_duration_seconds",
+                    "Time to enrich a search, parsing and serializing included",
+                )
+                .buckets(exponential_buckets(0.00005, 3.0, 11).unwrap()),
+            )
+            .unwrap(),
+            recos_per_search: Histogram::with_opts(
+                HistogramOpts::new("enrichment_recos_per_search", "Recos of each search read")
+                    .buckets(vec![
+                        0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0,
+                    ]),
+            )
+            .unwrap(),
+        };
+        for collector in [
+            Box::new(metrics.searches_read.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(metrics.searches_enriched.clone()),
+            Box::new(metrics.searches_failed.clone()),
+            Box::new(metrics.recos_dropped.clone()),
+            Box::new(metrics.unknown_airports.clone()),
+            Box::new(metrics.unknown_currencies.clone()),
+            Box::new(metrics.enrichment_duration.clone()),
+            Box::new(metrics.recos_per_search.clone()),
+        ] {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+
+    /// A search that could not be read, like an invalid JSON line.
+    pub fn record_read_error(&self) {
+        self.searches_read.inc();
+        self.searches_failed
+            .with_label_values(&["invalid_input"])
+            .inc();
+    }
+
+    /// Enriches a search read from the input with `enrich`, recording its outcome.
+    /// `nb_of_recos` counts the recos of the output, to count those dropped as duplicates.
+    pub fn observe<T>(
+        &self,
+        input_json: serde_json::Value,
+        enrich: impl FnOnce(serde_json::Value) -> Result<T, EnrichJsonError>,
+        nb_of_recos: impl FnOnce(&T) -> usize,
+    ) -> Result<T, EnrichJsonError> {
+        let nb_of_input_recos = input_json["recos"].as_array().map_or(0, Vec::len);
+        let unknown_currency = input_json["currency"]
+            .as_str()
+            .is_some_and(|currency| Currency::from_str(currency).is_err());
+        self.observe_enrichment(
+            nb_of_input_recos,
+            unknown_currency,
+            || enrich(input_json),
+            nb_of_recos,
+        )
+    }
+
+    /// Same as `observe`, for a search read as JSON text by `RawObject::from_slice`.
+    pub(crate) fn observe_raw<'a, T>(
+        &self,
+        raw_search: RawObject<'a>,
+        enrich: impl FnOnce(RawObject<'a>) -> Result<T, EnrichJsonError>,
+        nb_of_recos: impl FnOnce(&T) -> usize,
+    ) -> Result<T, EnrichJsonError> {
+        let nb_of_input_recos = raw_search.children.len();
+        let unknown_currency = raw_search
+            .field("currency")
+            .and_then(|currency| serde_json::from_str::<&str>(currency.get()).ok())
+            .is_some_and(|currency| Currency::from_str(currency).is_err());
+        self.observe_enrichment(
+            nb_of_input_recos,
+            unknown_currency,
+            || enrich(raw_search),
+            nb_of_recos,
+        )
+    }
+
+    fn observe_enrichment<T>(
+        &self,
+        nb_of_input_recos: usize,
+        unknown_currency: bool,
+        enrich: impl FnOnce() -> Result<T, EnrichJsonError>,
+        nb_of_recos: impl FnOnce(&T) -> usize,
+    ) -> Result<T, EnrichJsonError> {
+        self.searches_read.inc();
+        self.recos_per_search.observe(nb_of_input_recos as f64);
+
+        let start = Instant::now();
+        let result = enrich();
+        self.enrichment_duration
+            .observe(start.elapsed().as_secs_f64());
+
+        match &result {
+            Ok(output) => {
+                self.searches_enriched.inc();
+                let nb_of_duplicates = nb_of_input_recos.saturating_sub(nb_of_recos(output));
+                self.recos_dropped
+                    .with_label_values(&["duplicate"])
+                    .inc_by(nb_of_duplicates as u64);
+            }
+            Err(error) => {
+                let label = error_label(error);
+                self.searches_failed.with_label_values(&[label]).inc();
+                self.recos_dropped
+                    .with_label_values(&["failed_search"])
+                    .inc_by(nb_of_input_recos as u64);
+                if label == "missing_location_in_distance_calculation" {
+                    self.unknown_airports.inc();
+                }
+                // a currency without a rate is as unknown as one that does not parse
+                if unknown_currency || label == "missing_exchange_rate" {
+                    self.unknown_currencies.inc();
+                }
+            }
+        }
+        result
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency_exchange::ExchangeRatesError;
+
+    #[test]
+    fn test_observe() {
+        let metrics = EnrichmentMetrics::new();
+        let search = serde_json::json!({"currency": "EUR", "recos": [{}, {}, {}]});
+
+        let enriched = metrics.observe(search.clone(), |_| Ok(2), |nb_of_recos| *nb_of_recos);
+        assert_eq!(enriched.unwrap(), 2);
+        let failed = metrics.observe(
+            search,
+            |_| -> Result<usize, _> {
+                Err(EnrichJsonError::FailedToEnrichSearch(
+                    EnrichSearchError::MissingLocationInDistanceCalculation {
+                        origin_city: "XXX".to_string(),
+                        destination_city: "LIS".to_string(),
+                    },
+                ))
+            },
+            |nb_of_recos| *nb_of_recos,
+        );
+        assert!(failed.is_err());
+        let unknown_currency = serde_json::json!({"currency": "XXX", "recos": []});
+        let failed = metrics.observe(
+            unknown_currency,
+            |input_json| {
+                serde_json::from_value::<Currency>(input_json["currency"].clone())
+                    .map_err(EnrichJsonError::FailedToParseSearch)
+            },
+            |_| 0,
+        );
+        assert!(failed.is_err());
+        metrics.record_read_error();
+
+        let text = metrics.encode();
+        for line in [
+            "enrichment_searches_read_total 4",
+            "enrichment_searches_enriched_total 1",
+            "enrichment_searches_failed_total{error=\"missing_location_in_distance_calculation\"} 1",
+            "enrichment_searches_failed_total{error=\"failed_to_parse_search\"} 1",
+            "enrichment_searches_failed_total{error=\"invalid_input\"} 1",
+            "enrichment_recos_dropped_total{reason=\"duplicate\"} 1",
+            "enrichment_recos_dropped_total{reason=\"failed_search\"} 3",
+            "enrichment_unknown_airports_total 1",
+            "enrichment_unknown_currencies_total 1",
+            "enrichment_duration_seconds_count 3",
+            "enrichment_recos_per_search_bucket{le=\"2\"} 1",
+        ] {
+            assert!(text.contains(line), "{line} not in {text}");
+        }
+    }
+
+    #[test]
+    fn test_observe_missing_exchange_rate() {
+        let metrics = EnrichmentMetrics::new();
+        let search = serde_json::json!({"currency": "JPY", "recos": [{}]});
+
+        let failed = metrics.observe(
+            search,
+            |_| -> Result<usize, _> {
+                Err(EnrichJsonError::FailedToEnrichSearch(
+                    EnrichSearchError::EnrichReco(EnrichRecoError::ExchangeRate(
+                        ExchangeRatesError::MissingRate(Currency::JPY),
+                    )),
+                ))
+            },
+            |nb_of_recos| *nb_of_recos,
+        );
+        assert!(failed.is_err());
+
+        let text = metrics.encode();
+        for line in [
+            "enrichment_searches_failed_total{error=\"missing_exchange_rate\"} 1",
+            "enrichment_unknown_currencies_total 1",
+        ] {
+            assert!(text.contains(line), "{line} not in {text}");
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rdkafka::{
+    error::{KafkaError, RDKafkaErrorCode},
+    producer::{BaseProducer, BaseRecord, DeliveryResult, Producer, ProducerContext},
+    ClientConfig, ClientContext,
+};
+
+use super::{AlertSink, MonitoringError, PriceAlert};
+
+/// How long `flush` waits for the alerts to be delivered.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Records the alerts that failed to be delivered, as `send` only queues them.
+#[derive(Default)]
+struct DeliveryErrors {
+    /// Number of failed deliveries, and the first error.
+    errors: Mutex<(usize, Option<KafkaError>)>,
+}
+
+impl DeliveryErrors {
+    /// The failed deliveries since the last call.
+    fn take(&self) -> Option<MonitoringError> {
+        let mut errors = self
+            .errors
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        let (nb_of_alerts, error) = std::mem::take(&mut *errors);
+        error.map(|error| MonitoringError::KafkaDelivery {
+            nb_of_alerts,
+            error,
+        })
+    }
+}
+
+impl ClientContext for DeliveryErrors {}
+
+impl ProducerContext for DeliveryErrors {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, delivery_result: &DeliveryResult<'_>, _: ()) {
+        if let Err((error, _)) = delivery_result {
+            let mut errors = self
+                .errors
+                .lock()
+                .unwrap_or_else(|error| error.into_inner());
+            errors.0 += 1;
+            errors.1.get_or_insert_with(|| error.clone());
+        }
+    }
+}
+
+/// Sends alerts as JSON to a Kafka topic, keyed by OnD so that the alerts of an OnD stay ordered.
+/// Alerts that fail to be delivered are reported by `flush`.
+pub struct KafkaAlertSink {
+    producer: BaseProducer<DeliveryErrors>,
+    topic: String,
+}
+
+impl KafkaAlertSink {
+    /// `brokers` is a comma-separated list of `host:port`, like `localhost:9092`.
+    pub fn new(brokers: &str, topic: impl Into<String>) -> Result<Self, MonitoringError> {
+        Ok(KafkaAlertSink {
+            producer: ClientConfig::new()
+                .set("bootstrap.servers", brokers)
+                .create_with_context(DeliveryErrors::default())?,
+            topic: topic.into(),
+        })
+    }
+}
+
+impl AlertSink for KafkaAlertSink {
+    fn send(&mut self, alert: &PriceAlert) -> Result<(), MonitoringError> {
+        let payload = serde_json::to_string(alert)?;
+        loop {
+            let record = BaseRecord::to(&self.topic)
+                .key(&alert.ond)
+                .payload(&payload);
+            match self.producer.send(record) {
+                Ok(()) => break,
+                // waits for deliveries to free the queue
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) => {
+                    self.producer.poll(Duration::from_millis(100));
+                }
+                Err((error, _)) => return Err(error.into()),
+            }
+        }
+        self.producer.poll(Duration::ZERO);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), MonitoringError> {
+        self.producer.flush(FLUSH_TIMEOUT)?;
+        match self.producer.context().take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::cabin::Cabin;
+
+    #[test]
+    fn test_delivery_errors() {
+        // nothing listens on the port, so the alert times out
+        let mut sink = KafkaAlertSink {
+            producer: ClientConfig::new()
+                .set("bootstrap.servers", "127.0.0.1:1")
+                .set("message.timeout.ms", "100")
+                .create_with_context(DeliveryErrors::default())
+                .unwrap(),
+            topic: "alerts".to_string(),
+        };
+        let alert = PriceAlert {
+            search_id: "S1".to_string(),
+            search_date: NaiveDate::from_ymd_opt(2021, 11, 17).unwrap(),
+            ond: "PAR-LIS".to_string(),
+            airline: "TP".to_string(),
+            cabin: Some(Cabin::Economy),
+            price_eur: 100.0,
+            baseline_price_eur: 200.0,
+            stddev_price_eur: 10.0,
+            deviation_percent: Some(-50.0),
+            nb_of_stddevs: Some(-10.0),
+            nb_of_observations: 10,
+        };
+        sink.send(&alert).unwrap();
+        sink.send(&alert).unwrap();
+
+        assert!(matches!(
+            sink.flush(),
+            Err(MonitoringError::KafkaDelivery {
+                nb_of_alerts: 2,
+                ..
+            })
+        ));
+        assert!(sink.flush().is_ok());
+    }
+}
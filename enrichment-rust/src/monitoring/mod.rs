@@ -0,0 +1,427 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Write;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::{
+    cabin::Cabin,
+    search::{enriched_search::EnrichedSearch, Search},
+    serde_json_helpers::{
+        serialize_f64_2_decimals, serialize_f64_2_decimals_optional, ymd_date_format,
+    },
+};
+
+#[cfg(feature = "kafka")]
+pub mod kafka;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MonitoringError {
+    #[error("Failed to write alert: {0:?}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to serialize alert: {0:?}")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "kafka")]
+    #[error("Kafka error: {0:?}")]
+    Kafka(#[from] rdkafka::error::KafkaError),
+    #[cfg(feature = "kafka")]
+    #[error("Failed to deliver {nb_of_alerts} alerts to Kafka, first with: {error:?}")]
+    KafkaDelivery {
+        nb_of_alerts: usize,
+        error: rdkafka::error::KafkaError,
+    },
+}
+
+/// Options of `PriceMonitor`. A threshold set to `None` never alerts.
+#[derive(Clone, Copy, Debug)]
+pub struct MonitorOptions {
+    /// Number of cheapest prices kept per OnD, airline and cabin for the baseline.
+    pub window_size: usize,
+    /// Prices observed before a window alerts.
+    pub min_observations: usize,
+    /// Alerts when the cheapest price is this many standard deviations away from the mean.
+    pub max_stddevs: Option<f64>,
+    /// Alerts when the cheapest price is this many percents away from the mean.
+    pub max_deviation_percent: Option<f64>,
+}
+
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        MonitorOptions {
+            window_size: 100,
+            min_observations: 10,
+            max_stddevs: Some(3.0),
+            max_deviation_percent: None,
+        }
+    }
+}
+
+/// A cheapest price out of the baseline of its window.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PriceAlert {
+    pub search_id: String,
+    #[serde(with = "ymd_date_format")]
+    pub search_date: NaiveDate,
+    #[serde(rename = "OnD")]
+    pub ond: String,
+    pub airline: String,
+    /// Normalized main cabin of the recos, `None` when their cabin code is unknown.
+    pub cabin: Option<Cabin>,
+    #[serde(rename = "price_EUR", serialize_with = "serialize_f64_2_decimals")]
+    pub price_eur: f64,
+    /// Mean of the window, before this price.
+    #[serde(
+        rename = "baseline_price_EUR",
+        serialize_with = "serialize_f64_2_decimals"
+    )]
+    pub baseline_price_eur: f64,
+    #[serde(
+        rename = "stddev_price_EUR",
+        serialize_with = "serialize_f64_2_decimals"
+    )]
+    pub stddev_price_eur: f64,
+    /// Negative for drops. `None` when the baseline is not positive.
+    #[serde(serialize_with = "serialize_f64_2_decimals_optional")]
+    pub deviation_percent: Option<f64>,
+    /// `None` when all the prices of the window are equal.
+    pub nb_of_stddevs: Option<f64>,
+    pub nb_of_observations: u64,
+}
+
+/// Last cheapest prices of an OnD, airline and normalized cabin.
+#[derive(Default)]
+struct Window {
+    prices: VecDeque<f64>,
+}
+
+/// How far a price is from the prices of its window.
+struct Deviation {
+    mean: f64,
+    stddev: f64,
+    /// `None` when the mean is not positive.
+    percent: Option<f64>,
+    /// `None` when all the prices of the window are equal.
+    nb_of_stddevs: Option<f64>,
+}
+
+impl Window {
+    fn mean_and_stddev(&self) -> (f64, f64) {
+        let count = self.prices.len() as f64;
+        let mean = self.prices.iter().sum::<f64>() / count;
+        let variance = self
+            .prices
+            .iter()
+            .map(|price| (price - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        (mean, variance.sqrt())
+    }
+
+    /// The deviation of a price from the window when it is beyond a threshold, once the window
+    /// has enough prices.
+    fn alert(&self, price: f64, options: &MonitorOptions) -> Option<Deviation> {
+        if self.prices.len() < options.min_observations.max(1) {
+            return None;
+        }
+        let (mean, stddev) = self.mean_and_stddev();
+        let percent = (mean > 0.0).then(|| (price - mean) / mean * 100.0);
+        let nb_of_stddevs = (stddev > 0.0).then(|| (price - mean) / stddev);
+        let too_many_stddevs = options
+            .max_stddevs
+            .zip(nb_of_stddevs)
+            .is_some_and(|(max, stddevs)| stddevs.abs() > max);
+        let too_far = options
+            .max_deviation_percent
+            .zip(percent)
+            .is_some_and(|(max, percent)| percent.abs() > max);
+        (too_many_stddevs || too_far).then_some(Deviation {
+            mean,
+            stddev,
+            percent,
+            nb_of_stddevs,
+        })
+    }
+
+    fn push(&mut self, price: f64, window_size: usize) {
+        if self.prices.len() >= window_size {
+            self.prices.pop_front();
+        }
+        self.prices.push_back(price);
+    }
+}
+
+/// Monitors the cheapest price of each search per OnD, airline and cabin, against the mean and
+/// standard deviation of the last cheapest prices of the same OnD, airline and cabin. Searches
+/// are expected in the order of the stream.
+pub struct PriceMonitor {
+    options: MonitorOptions,
+    windows: HashMap<(String, String, Option<Cabin>), Window>,
+}
+
+impl PriceMonitor {
+    pub fn new(options: MonitorOptions) -> Self {
+        PriceMonitor {
+            options,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Adds the cheapest prices of an enriched search to their windows, and returns the alerts
+    /// they raise, sorted by airline and cabin.
+    pub fn observe(
+        &mut self,
+        search: &Search,
+        enriched_search: &EnrichedSearch,
+    ) -> Vec<PriceAlert> {
+        let ond = format!("{}-{}", search.origin_city, search.destination_city);
+        // by normalized cabin, so that the prices of J and C fares make a single window
+        let mut cheapest: BTreeMap<(&str, Option<Cabin>), f64> = BTreeMap::new();
+        for reco in &enriched_search.recos {
+            cheapest
+                .entry((&reco.main_marketing_airline, reco.main_cabin_class))
+                .and_modify(|price| *price = price.min(reco.price_eur))
+                .or_insert(reco.price_eur);
+        }
+
+        let mut alerts = vec![];
+        for ((airline, cabin), price) in cheapest {
+            let window = self
+                .windows
+                .entry((ond.clone(), airline.to_string(), cabin))
+                .or_default();
+            if let Some(deviation) = window.alert(price, &self.options) {
+                alerts.push(PriceAlert {
+                    search_id: search.search_id.to_string(),
+                    search_date: search.search_date,
+                    ond: ond.clone(),
+                    airline: airline.to_string(),
+                    cabin,
+                    price_eur: price,
+                    baseline_price_eur: deviation.mean,
+                    stddev_price_eur: deviation.stddev,
+                    deviation_percent: deviation.percent,
+                    nb_of_stddevs: deviation.nb_of_stddevs,
+                    nb_of_observations: window.prices.len() as u64,
+                });
+            }
+            window.push(price, self.options.window_size);
+        }
+        alerts
+    }
+}
+
+/// Where alerts go.
+pub trait AlertSink {
+    fn send(&mut self, alert: &PriceAlert) -> Result<(), MonitoringError>;
+
+    /// Waits for the alerts sent to be delivered.
+    fn flush(&mut self) -> Result<(), MonitoringError>;
+}
+
+/// Writes alerts as JSON, one per line.
+pub struct NdjsonAlertSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> NdjsonAlertSink<W> {
+    pub fn new(writer: W) -> Self {
+        NdjsonAlertSink { writer }
+    }
+}
+
+impl<W: Write> AlertSink for NdjsonAlertSink<W> {
+    fn send(&mut self, alert: &PriceAlert) -> Result<(), MonitoringError> {
+        serde_json::to_writer(&mut self.writer, alert)?;
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), MonitoringError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{enrich_to_search, sample_search};
+
+    fn window(prices: &[f64]) -> Window {
+        Window {
+            prices: prices.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn test_window_keeps_the_last_prices() {
+        let mut window = window(&[]);
+        for price in [100.0, 110.0, 120.0, 130.0] {
+            window.push(price, 3);
+        }
+        assert_eq!(window.prices, [110.0, 120.0, 130.0]);
+        assert_eq!(window.mean_and_stddev().0, 120.0);
+    }
+
+    #[test]
+    fn test_min_observations() {
+        let options = MonitorOptions {
+            min_observations: 3,
+            max_deviation_percent: Some(10.0),
+            ..MonitorOptions::default()
+        };
+        assert!(window(&[100.0, 110.0]).alert(50.0, &options).is_none());
+        assert!(window(&[100.0, 110.0, 90.0])
+            .alert(50.0, &options)
+            .is_some());
+
+        // an empty window never alerts
+        let options = MonitorOptions {
+            min_observations: 0,
+            ..options
+        };
+        assert!(window(&[]).alert(50.0, &options).is_none());
+    }
+
+    #[test]
+    fn test_equal_prices_only_alert_on_percentage() {
+        let stddevs_only = MonitorOptions {
+            min_observations: 1,
+            ..MonitorOptions::default()
+        };
+        assert!(window(&[100.0, 100.0]).alert(50.0, &stddevs_only).is_none());
+
+        let options = MonitorOptions {
+            max_deviation_percent: Some(10.0),
+            ..stddevs_only
+        };
+        assert!(window(&[100.0, 100.0]).alert(95.0, &options).is_none());
+        let deviation = window(&[100.0, 100.0]).alert(50.0, &options).unwrap();
+        assert_eq!(deviation.mean, 100.0);
+        assert_eq!(deviation.stddev, 0.0);
+        assert_eq!(deviation.percent, Some(-50.0));
+        assert_eq!(deviation.nb_of_stddevs, None);
+    }
+
+    #[test]
+    fn test_free_prices_do_not_alert_on_percentage() {
+        let options = MonitorOptions {
+            min_observations: 1,
+            max_stddevs: None,
+            max_deviation_percent: Some(10.0),
+            ..MonitorOptions::default()
+        };
+        assert!(window(&[0.0, 0.0]).alert(50.0, &options).is_none());
+
+        let options = MonitorOptions {
+            max_stddevs: Some(3.0),
+            ..options
+        };
+        let deviation = window(&[-10.0, 10.0]).alert(50.0, &options).unwrap();
+        assert_eq!(deviation.percent, None);
+        assert_eq!(deviation.nb_of_stddevs, Some(5.0));
+    }
+
+    #[test]
+    fn test_thresholds() {
+        // mean 100, standard deviation 10
+        let prices = [90.0, 110.0];
+        let options = MonitorOptions {
+            min_observations: 1,
+            ..MonitorOptions::default()
+        };
+        assert!(window(&prices).alert(75.0, &options).is_none());
+        let deviation = window(&prices).alert(65.0, &options).unwrap();
+        assert_eq!(deviation.nb_of_stddevs, Some(-3.5));
+
+        let no_stddevs = MonitorOptions {
+            max_stddevs: None,
+            ..options
+        };
+        assert!(window(&prices).alert(65.0, &no_stddevs).is_none());
+        let percent = MonitorOptions {
+            max_deviation_percent: Some(30.0),
+            ..no_stddevs
+        };
+        assert!(window(&prices).alert(75.0, &percent).is_none());
+        let deviation = window(&prices).alert(65.0, &percent).unwrap();
+        assert_eq!(deviation.percent, Some(-35.0));
+        assert_eq!(deviation.nb_of_stddevs, Some(-3.5));
+    }
+
+    #[test]
+    fn test_windows_by_normalized_cabin() {
+        let mut monitor = PriceMonitor::new(MonitorOptions::default());
+        for booking_class in ["C", "J", "D"] {
+            let mut search = sample_search();
+            for flight in search["recos"][2]["flights"].as_array_mut().unwrap() {
+                flight["cabin"] = booking_class.into();
+            }
+            let (search, enriched_search) = enrich_to_search(search);
+            monitor.observe(&search, &enriched_search);
+        }
+
+        // KL and TP in economy, and TP in business
+        assert_eq!(monitor.windows.len(), 3);
+        let business = (
+            "PAR-LIS".to_string(),
+            "TP".to_string(),
+            Some(Cabin::Business),
+        );
+        assert_eq!(monitor.windows[&business].prices.len(), 3);
+    }
+
+    #[test]
+    fn test_price_monitoring() {
+        let enrich = |price: &str| {
+            let mut search = sample_search();
+            search["recos"][2]["price"] = price.into();
+            enrich_to_search(search)
+        };
+
+        let mut monitor = PriceMonitor::new(MonitorOptions {
+            window_size: 4,
+            min_observations: 4,
+            ..MonitorOptions::default()
+        });
+        for price in ["190.00", "200.00", "210.00", "200.00", "205.00"] {
+            let (search, enriched_search) = enrich(price);
+            // KL prices never vary, TP ones stay within 3 standard deviations
+            assert_eq!(monitor.observe(&search, &enriched_search), vec![]);
+        }
+        let (search, enriched_search) = enrich("100.00");
+        let alerts = monitor.observe(&search, &enriched_search);
+        assert_eq!(alerts.len(), 1);
+        // baseline of the last 4 prices: 200, 210, 200 and 205
+        assert_eq!(alerts[0].airline, "TP");
+        assert_eq!(alerts[0].baseline_price_eur, 203.75);
+        assert!(alerts[0].nb_of_stddevs.unwrap() < -3.0);
+
+        let mut monitor = PriceMonitor::new(MonitorOptions {
+            min_observations: 1,
+            max_stddevs: None,
+            max_deviation_percent: Some(10.0),
+            ..MonitorOptions::default()
+        });
+        let (search, enriched_search) = enrich("200.00");
+        monitor.observe(&search, &enriched_search);
+        let (search, enriched_search) = enrich("185.00");
+        assert_eq!(monitor.observe(&search, &enriched_search), vec![]);
+        let (search, enriched_search) = enrich("150.00");
+        let alerts = monitor.observe(&search, &enriched_search);
+
+        let mut ndjson = vec![];
+        let mut sink = NdjsonAlertSink::new(&mut ndjson);
+        for alert in &alerts {
+            sink.send(alert).unwrap();
+        }
+        sink.flush().unwrap();
+        let alert: serde_json::Value = serde_json::from_slice(&ndjson).unwrap();
+        assert_eq!(alert["OnD"], "PAR-LIS");
+        assert_eq!(alert["cabin"], "M");
+        assert_eq!(alert["price_EUR"], 150.0);
+        assert_eq!(alert["baseline_price_EUR"], 192.5);
+        assert_eq!(alert["deviation_percent"], -22.08);
+        assert_eq!(alert["search_date"], "2021-11-17");
+    }
+}
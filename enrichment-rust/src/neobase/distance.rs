@@ -0,0 +1,172 @@
+use serde::Serialize;
+
+const KM_PER_STATUTE_MILE: f64 = 1.609344;
+const KM_PER_NAUTICAL_MILE: f64 = 1.852;
+
+/// How distances between two points are computed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DistanceModel {
+    /// Haversine formula on a sphere of radius 6371 km, as the Python `neobase` package does.
+    #[default]
+    Spherical,
+    /// Vincenty's formula on the WGS-84 ellipsoid, as used by the IATA TPM/MPM rules.
+    /// Falls back to the spherical model for nearly antipodal points, where it does not converge.
+    Wgs84Geodesic,
+}
+
+/// A distance, convertible to the units used in the industry.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Distance {
+    km: f64,
+}
+
+impl Distance {
+    pub fn from_km(km: f64) -> Self {
+        Distance { km }
+    }
+
+    pub fn km(&self) -> f64 {
+        self.km
+    }
+
+    pub fn statute_miles(&self) -> f64 {
+        self.km / KM_PER_STATUTE_MILE
+    }
+
+    pub fn nautical_miles(&self) -> f64 {
+        self.km / KM_PER_NAUTICAL_MILE
+    }
+
+    /// Rounded to the nearest km, as Python's `round()` does (up to ties).
+    pub fn round_km(&self) -> u64 {
+        self.km.round() as u64
+    }
+
+    pub fn round_statute_miles(&self) -> u64 {
+        self.statute_miles().round() as u64
+    }
+
+    pub fn round_nautical_miles(&self) -> u64 {
+        self.nautical_miles().round() as u64
+    }
+}
+
+impl DistanceModel {
+    pub fn distance(&self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Distance {
+        let km = match self {
+            DistanceModel::Spherical => haversine_distance_km(lat1, lon1, lat2, lon2),
+            DistanceModel::Wgs84Geodesic => vincenty_distance_km(lat1, lon1, lat2, lon2)
+                .unwrap_or_else(|| haversine_distance_km(lat1, lon1, lat2, lon2)),
+        };
+        Distance::from_km(km)
+    }
+}
+
+pub(super) fn haversine_distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    const R: f64 = 6371.0; // Radius of the Earth in km
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+
+    let a = (d_lat / 2.0).sin() * (d_lat / 2.0).sin()
+        + lat1.to_radians().cos()
+            * lat2.to_radians().cos()
+            * (d_lon / 2.0).sin()
+            * (d_lon / 2.0).sin();
+    let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
+    R * c
+}
+
+/// Vincenty's inverse formula. Returns `None` when the iteration does not converge.
+fn vincenty_distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Option<f64> {
+    const A: f64 = 6_378_137.0; // WGS-84 semi-major axis in m
+    const F: f64 = 1.0 / 298.257_223_563; // WGS-84 flattening
+    const B: f64 = (1.0 - F) * A;
+    const MAX_ITERATIONS: usize = 200;
+
+    let l = (lon2 - lon1).to_radians();
+    let u1 = ((1.0 - F) * lat1.to_radians().tan()).atan();
+    let u2 = ((1.0 - F) * lat2.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..MAX_ITERATIONS {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+            + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2))
+        .sqrt();
+        if sin_sigma == 0.0 {
+            return Some(0.0); // coincident points
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+        // on the equator, cos_sq_alpha is 0
+        let cos_2_sigma_m = if cos_sq_alpha != 0.0 {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha
+        } else {
+            0.0
+        };
+        let c = F / 16.0 * cos_sq_alpha * (4.0 + F * (4.0 - 3.0 * cos_sq_alpha));
+
+        let previous_lambda = lambda;
+        lambda = l
+            + (1.0 - c)
+                * F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2_sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2_sigma_m.powi(2))));
+
+        if (lambda - previous_lambda).abs() < 1e-12 {
+            let u_sq = cos_sq_alpha * (A * A - B * B) / (B * B);
+            let big_a =
+                1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+            let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+            let delta_sigma = big_b
+                * sin_sigma
+                * (cos_2_sigma_m
+                    + big_b / 4.0
+                        * (cos_sigma * (-1.0 + 2.0 * cos_2_sigma_m.powi(2))
+                            - big_b / 6.0
+                                * cos_2_sigma_m
+                                * (-3.0 + 4.0 * sin_sigma.powi(2))
+                                * (-3.0 + 4.0 * cos_2_sigma_m.powi(2))));
+            return Some(B * big_a * (sigma - delta_sigma) / 1000.0);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vincenty_reference_distance() {
+        // Flinders Peak to Buninyong, the example of Vincenty's paper: 54972.271 m
+        let distance = DistanceModel::Wgs84Geodesic.distance(
+            -37.951_033_42,
+            144.424_867_89,
+            -37.652_821_14,
+            143.926_495_54,
+        );
+        assert!((distance.km() - 54.972_271).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_rounding_and_units() {
+        let distance = Distance::from_km(396.6);
+        assert_eq!(distance.round_km(), 397);
+        assert_eq!(distance.round_statute_miles(), 246);
+        assert_eq!(distance.round_nautical_miles(), 214);
+    }
+
+    #[test]
+    fn test_antipodal_points_fall_back_to_spherical() {
+        let distance = DistanceModel::Wgs84Geodesic.distance(0.0, 0.0, 0.5, 179.7);
+        assert!((distance.km() - haversine_distance_km(0.0, 0.0, 0.5, 179.7)).abs() < 1e-9);
+    }
+}
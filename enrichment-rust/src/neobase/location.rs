@@ -0,0 +1,239 @@
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::serde_json_helpers::ymd_date_format_optional;
+
+/// A raw line of the NeoBase file. Every column is optional so that trimmed-down files load too.
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+pub(super) struct Record {
+    iata_code: String,
+    icao_code: String,
+    faa_code: String,
+    // is_geonames: String,
+    geoname_id: String,
+    // envelope_id: String,
+    name: String,
+    asciiname: String,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    // fclass: String,
+    fcode: String,
+    page_rank: Option<f64>,
+    #[serde(with = "ymd_date_format_optional")]
+    date_from: Option<NaiveDate>,
+    #[serde(with = "ymd_date_format_optional")]
+    date_until: Option<NaiveDate>,
+    // comment: String,
+    country_code: String,
+    // cc2: String,
+    country_name: String,
+    continent_name: String,
+    adm1_code: String,
+    adm1_name_utf: String,
+    // adm1_name_ascii: String,
+    adm2_code: String,
+    adm2_name_utf: String,
+    // adm2_name_ascii: String,
+    // adm3_code: String,
+    // adm4_code: String,
+    population: String,
+    elevation: String,
+    // gtopo30: String,
+    timezone: String,
+    gmt_offset: String,
+    // dst_offset: String,
+    // raw_offset: String,
+    // moddate: String,
+    city_code_list: String,
+    city_name_list: String,
+    // city_detail_list: String,
+    // tvl_por_list: String,
+    // iso31662: String,
+    location_type: String,
+    wiki_link: String,
+    alt_name_section: String,
+    // wac: String,
+    // wac_name: String,
+    ccy_code: String,
+    unlc_list: String,
+    // uic_list: String,
+    // geoname_lat: String,
+    // geoname_lon: String,
+}
+
+/// Kind of a NeoBase location, from its `location_type` column.
+/// A location can have several types: "CA" is both a city and an airport.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LocationType {
+    City,
+    Airport,
+    Heliport,
+    RailStation,
+    BusStation,
+    Port,
+    GroundStation,
+    OffPoint,
+}
+
+impl LocationType {
+    fn from_neobase_char(c: char) -> Option<Self> {
+        match c {
+            'C' => Some(LocationType::City),
+            'A' => Some(LocationType::Airport),
+            'H' => Some(LocationType::Heliport),
+            'R' => Some(LocationType::RailStation),
+            'B' => Some(LocationType::BusStation),
+            'P' => Some(LocationType::Port),
+            'G' => Some(LocationType::GroundStation),
+            'O' => Some(LocationType::OffPoint),
+            _ => None,
+        }
+    }
+}
+
+/// An alternate name of a location, in a given language ("" when unspecified).
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AltName {
+    pub lang: String,
+    pub name: String,
+}
+
+/// A NeoBase record, valid between `date_from` and `date_until`.
+#[derive(Serialize, Clone, Debug)]
+pub struct Location {
+    pub iata_code: String,
+    pub icao_code: Option<String>,
+    pub faa_code: Option<String>,
+    pub geoname_id: Option<u64>,
+    pub name: String,
+    pub asciiname: String,
+    pub alt_names: Vec<AltName>,
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    pub fcode: String,
+    pub location_types: Vec<LocationType>,
+    pub page_rank: Option<f64>,
+    #[serde(with = "ymd_date_format_optional")]
+    pub date_from: Option<NaiveDate>,
+    #[serde(with = "ymd_date_format_optional")]
+    pub date_until: Option<NaiveDate>,
+    pub country_code: String,
+    pub country_name: String,
+    pub continent_name: String,
+    pub adm1_code: String,
+    pub adm1_name: String,
+    pub adm2_code: String,
+    pub adm2_name: String,
+    pub population: Option<u64>,
+    pub elevation: Option<i64>,
+    pub timezone: String,
+    pub gmt_offset: Option<f64>,
+    pub city_code_list: Vec<String>,
+    pub city_name_list: Vec<String>,
+    pub currency_code: String,
+    pub unlocode_list: Vec<String>,
+    pub wiki_link: String,
+}
+
+fn non_empty(s: String) -> Option<String> {
+    (!s.is_empty()).then_some(s)
+}
+
+impl From<Record> for Location {
+    fn from(record: Record) -> Self {
+        Location {
+            lat: record.latitude,
+            lng: record.longitude,
+            city_code_list: record
+                .city_code_list
+                .split(',')
+                .map(|s| s.to_string())
+                .collect(),
+            city_name_list: record
+                .city_name_list
+                .split('=')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect(),
+            location_types: record
+                .location_type
+                .chars()
+                .filter_map(LocationType::from_neobase_char)
+                .collect(),
+            // "lang|name|qualifiers=lang|name|qualifiers..."
+            alt_names: record
+                .alt_name_section
+                .split('=')
+                .filter_map(|entry| {
+                    let mut fields = entry.split('|');
+                    let lang = fields.next()?.to_string();
+                    let name = fields.next().filter(|name| !name.is_empty())?.to_string();
+                    Some(AltName { lang, name })
+                })
+                .collect(),
+            // "FRCDG|=FRPAR|h..."
+            unlocode_list: record
+                .unlc_list
+                .split('=')
+                .filter_map(|entry| entry.split('|').next())
+                .filter(|code| !code.is_empty())
+                .map(|code| code.to_string())
+                .collect(),
+            iata_code: record.iata_code,
+            icao_code: non_empty(record.icao_code),
+            faa_code: non_empty(record.faa_code),
+            geoname_id: record.geoname_id.parse().ok().filter(|id| *id != 0),
+            name: record.name,
+            asciiname: record.asciiname,
+            fcode: record.fcode,
+            page_rank: record.page_rank,
+            date_from: record.date_from,
+            date_until: record.date_until,
+            country_code: record.country_code,
+            country_name: record.country_name,
+            continent_name: record.continent_name,
+            adm1_code: record.adm1_code,
+            adm1_name: record.adm1_name_utf,
+            adm2_code: record.adm2_code,
+            adm2_name: record.adm2_name_utf,
+            population: record.population.parse().ok(),
+            elevation: record.elevation.parse().ok(),
+            timezone: record.timezone,
+            gmt_offset: record.gmt_offset.parse().ok(),
+            currency_code: record.ccy_code,
+            wiki_link: record.wiki_link,
+        }
+    }
+}
+
+impl Location {
+    /// A record is valid at `date` if it falls between its (inclusive) `date_from` and `date_until`.
+    /// Missing bounds are open.
+    pub fn is_valid_at(&self, date: NaiveDate) -> bool {
+        self.date_from.is_none_or(|from| from <= date)
+            && self.date_until.is_none_or(|until| date <= until)
+    }
+
+    pub fn is_airport(&self) -> bool {
+        self.location_types.contains(&LocationType::Airport) || self.fcode == "AIRP"
+    }
+
+    pub fn is_rail_station(&self) -> bool {
+        self.location_types.contains(&LocationType::RailStation)
+            || matches!(self.fcode.as_str(), "RSTN" | "RSTP")
+    }
+
+    pub fn is_bus_station(&self) -> bool {
+        self.location_types.contains(&LocationType::BusStation)
+            || matches!(self.fcode.as_str(), "BUSTN" | "BUSTP")
+    }
+
+    /// Case-insensitive substring match on the name, the ASCII name and the alternate names.
+    pub fn name_matches(&self, lowercase_query: &str) -> bool {
+        std::iter::once(&self.name)
+            .chain(std::iter::once(&self.asciiname))
+            .chain(self.alt_names.iter().map(|alt_name| &alt_name.name))
+            .any(|name| name.to_lowercase().contains(lowercase_query))
+    }
+}
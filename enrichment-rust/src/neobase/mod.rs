@@ -1,132 +1,477 @@
-use std::{collections::HashMap, fs::File};
-
-#[derive(serde::Deserialize)]
-struct Record {
-    iata_code: String,
-    // icao_code: String,
-    // faa_code: String,
-    // is_geonames: String,
-    // geoname_id: String,
-    // envelope_id: String,
-    // name: String,
-    // asciiname: String,
-    latitude: Option<f64>,
-    longitude: Option<f64>,
-    // fclass: String,
-    // fcode: String,
-    // page_rank: String,
-    // date_from: String,
-    // date_until: String,
-    // comment: String,
-    country_code: String,
-    // cc2: String,
-    // country_name: String,
-    // continent_name: String,
-    // adm1_code: String,
-    // adm1_name_utf: String,
-    // adm1_name_ascii: String,
-    // adm2_code: String,
-    // adm2_name_utf: String,
-    // adm2_name_ascii: String,
-    // adm3_code: String,
-    // adm4_code: String,
-    // population: String,
-    // elevation: String,
-    // gtopo30: String,
-    // timezone: String,
-    // gmt_offset: String,
-    // dst_offset: String,
-    // raw_offset: String,
-    // moddate: String,
-    city_code_list: String,
-    // city_name_list: String,
-    // city_detail_list: String,
-    // tvl_por_list: String,
-    // iso31662: String,
-    // location_type: String,
-    // wiki_link: String,
-    // alt_name_section: String,
-    // wac: String,
-    // wac_name: String,
-    // ccy_code: String,
-    // unlc_list: String,
-    // uic_list: String,
-    // geoname_lat: String,
-    // geoname_lon: String,
-}
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Read,
+    path::Path,
+};
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use self::location::Record;
+use self::spatial::SpatialGrid;
+
+pub use self::distance::{Distance, DistanceModel};
+pub use self::location::{AltName, Location, LocationType};
+
+mod distance;
+mod location;
+mod spatial;
 
-struct Location {
-    lat: Option<f64>,
-    lng: Option<f64>,
-    country_code: String,
-    city_code_list: Vec<String>,
+#[derive(Debug, thiserror::Error)]
+pub enum NeobaseError {
+    #[error("Failed to open NeoBase file: {0:?}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to read NeoBase record: {0:?}")]
+    Csv(#[from] csv::Error),
 }
 
-fn get_geodata(filepath: &str) -> HashMap<String, Location> {
+/// IATA codes get reused over time (TXL closed, BER opened...), so every record of a code is kept,
+/// in file order.
+fn get_geodata(filepath: &str) -> HashMap<String, Vec<Location>> {
     let csv_file = File::open(filepath).expect("File not found");
+    read_geodata(csv_file)
+}
+
+fn read_geodata<R: Read>(reader: R) -> HashMap<String, Vec<Location>> {
+    try_read_geodata(reader).expect("Error parsing record")
+}
+
+fn try_read_geodata<R: Read>(reader: R) -> Result<HashMap<String, Vec<Location>>, csv::Error> {
     let mut csv_reader = csv::ReaderBuilder::new()
         .delimiter(b'^')
-        .from_reader(csv_file);
+        .from_reader(reader);
 
-    let mut airports = HashMap::new();
+    let mut airports: HashMap<String, Vec<Location>> = HashMap::new();
 
     for result in csv_reader.deserialize() {
-        let record: Record = result.expect("Error parsing record");
-        let airport = Location {
-            lat: record.latitude,
-            lng: record.longitude,
-            country_code: record.country_code,
-            city_code_list: record
-                .city_code_list
-                .split(',')
-                .map(|s| s.to_string())
-                .collect(),
-        };
-        airports.insert(record.iata_code, airport);
+        let record: Record = result?;
+        let airport = Location::from(record);
+        airports
+            .entry(airport.iata_code.clone())
+            .or_default()
+            .push(airport);
+    }
+
+    Ok(airports)
+}
+
+fn build_spatial_grid(locations: &HashMap<String, Vec<Location>>) -> SpatialGrid {
+    let mut grid = SpatialGrid::new();
+    for (code, records) in locations {
+        for (index, loc) in records.iter().enumerate() {
+            if let (Some(lat), Some(lng)) = (loc.lat, loc.lng) {
+                grid.insert(code, index, lat, lng);
+            }
+        }
+    }
+    grid
+}
+
+/// A location found by a spatial query, with its distance to the queried point.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct NearbyLocation {
+    pub code: String,
+    pub distance_km: f64,
+}
+
+/// Half the circumference of the Earth: no location can be farther than that.
+const MAX_DISTANCE_KM: f64 = 20_016.0;
+
+/// The kinds of codes a location can be looked up by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CodeType {
+    Iata,
+    Icao,
+    Faa,
+    Unlocode,
+    Geonames,
+}
+
+type CodeIndex = HashMap<(CodeType, String), Vec<(String, usize)>>;
+
+/// Maps the non-IATA codes of every record to `(IATA code, index of the record)`.
+fn build_code_index(locations: &HashMap<String, Vec<Location>>) -> CodeIndex {
+    let mut index: CodeIndex = HashMap::new();
+    for (code, records) in locations {
+        for (record_index, loc) in records.iter().enumerate() {
+            let codes = loc
+                .icao_code
+                .iter()
+                .map(|icao| (CodeType::Icao, icao.clone()))
+                .chain(loc.faa_code.iter().map(|faa| (CodeType::Faa, faa.clone())))
+                .chain(
+                    loc.geoname_id
+                        .iter()
+                        .map(|id| (CodeType::Geonames, id.to_string())),
+                )
+                .chain(
+                    loc.unlocode_list
+                        .iter()
+                        .map(|unlc| (CodeType::Unlocode, unlc.clone())),
+                );
+            for key in codes {
+                index
+                    .entry(key)
+                    .or_default()
+                    .push((code.clone(), record_index));
+            }
+        }
     }
+    index
+}
 
-    airports
+/// Picks, among the records of a code, the one valid at `date`.
+/// Without a date, or when no record was valid at that date, the currently valid record is used
+/// (the one without `date_until`, or the last one).
+fn select_record_at<'a, I>(records: I, date: Option<NaiveDate>) -> Option<&'a Location>
+where
+    I: DoubleEndedIterator<Item = &'a Location> + Clone,
+{
+    date.and_then(|date| records.clone().rev().find(|loc| loc.is_valid_at(date)))
+        .or_else(|| records.clone().rev().find(|loc| loc.date_until.is_none()))
+        .or_else(|| records.last())
 }
+
+/// All the records of a code valid at `date`, like the city and the airport records of NCE, with
+/// the fallbacks of `select_record_at`.
+fn records_at(records: &[Location], date: Option<NaiveDate>) -> Vec<&Location> {
+    if let Some(date) = date {
+        let valid: Vec<&Location> = records.iter().filter(|loc| loc.is_valid_at(date)).collect();
+        if !valid.is_empty() {
+            return valid;
+        }
+    }
+    let current: Vec<&Location> = records
+        .iter()
+        .filter(|loc| loc.date_until.is_none())
+        .collect();
+    if !current.is_empty() {
+        return current;
+    }
+    records.last().into_iter().collect()
+}
+
 pub struct Locations {
-    locations: HashMap<String, Location>,
+    locations: HashMap<String, Vec<Location>>,
+    grid: SpatialGrid,
+    code_index: CodeIndex,
+    distance_model: DistanceModel,
 }
 
 impl Locations {
     pub fn new() -> Self {
+        Self::from_file("src/neobase/data.csv")
+    }
+
+    /// Loads a NeoBase `^`-separated file.
+    pub fn from_file(filepath: &str) -> Self {
+        Self::from_geodata(get_geodata(filepath))
+    }
+
+    /// Loads NeoBase `^`-separated data from any reader.
+    pub fn from_reader<R: Read>(reader: R) -> Self {
+        Self::from_geodata(read_geodata(reader))
+    }
+
+    /// Loads a NeoBase file, failing instead of panicking on invalid data.
+    pub fn try_from_file(filepath: impl AsRef<Path>) -> Result<Self, NeobaseError> {
+        Self::try_from_reader(File::open(filepath)?)
+    }
+
+    pub fn try_from_reader<R: Read>(reader: R) -> Result<Self, NeobaseError> {
+        Ok(Self::from_geodata(try_read_geodata(reader)?))
+    }
+
+    /// Number of codes.
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    fn from_geodata(locations: HashMap<String, Vec<Location>>) -> Self {
+        let grid = build_spatial_grid(&locations);
+        let code_index = build_code_index(&locations);
         Locations {
-            locations: get_geodata("src/neobase/data.csv"),
+            locations,
+            grid,
+            code_index,
+            distance_model: DistanceModel::default(),
         }
     }
 
-    pub fn get_country_from_city(&self, city: &str) -> String {
-        match self.locations.get(city) {
+    /// Selects how distances are computed. Spherical by default.
+    pub fn with_distance_model(mut self, distance_model: DistanceModel) -> Self {
+        self.distance_model = distance_model;
+        self
+    }
+
+    /// Resolves an IATA code as of `date`.
+    /// Without a date, or when no record of the code was valid at that date, the currently valid
+    /// record is used (the one without `date_until`, or the last one of the file).
+    pub fn get_location(&self, code: &str, date: Option<NaiveDate>) -> Option<&Location> {
+        select_record_at(self.locations.get(code)?.iter(), date)
+    }
+
+    /// Resolves a code of any type as of `date`.
+    pub fn get_location_by_code(
+        &self,
+        code_type: CodeType,
+        code: &str,
+        date: Option<NaiveDate>,
+    ) -> Option<&Location> {
+        if code_type == CodeType::Iata {
+            return self.get_location(code, date);
+        }
+        let records = self.code_index.get(&(code_type, code.to_string()))?;
+        select_record_at(
+            records
+                .iter()
+                .map(|(iata_code, index)| &self.locations[iata_code][*index]),
+            date,
+        )
+    }
+
+    /// Locations valid at `date` whose name or one of its alternate names contains `query`,
+    /// ignoring case. All the records of a code valid at `date` are searched, like the city and
+    /// the airport records of NCE, and the best ranked matching one is kept per code. The best
+    /// ranked locations come first.
+    pub fn search_by_name(&self, query: &str, date: Option<NaiveDate>) -> Vec<&Location> {
+        let lowercase_query = query.to_lowercase();
+        let mut found: Vec<&Location> = self
+            .locations
+            .values()
+            .filter_map(|records| {
+                records_at(records, date)
+                    .into_iter()
+                    .filter(|loc| loc.name_matches(&lowercase_query))
+                    .max_by(|a, b| {
+                        a.page_rank
+                            .unwrap_or(0.0)
+                            .total_cmp(&b.page_rank.unwrap_or(0.0))
+                    })
+            })
+            .collect();
+
+        found.sort_by(|a, b| {
+            b.page_rank
+                .unwrap_or(0.0)
+                .total_cmp(&a.page_rank.unwrap_or(0.0))
+                .then_with(|| a.iata_code.cmp(&b.iata_code))
+        });
+        found
+    }
+
+    pub fn get_country_from_city(&self, city: &str, date: Option<NaiveDate>) -> String {
+        match self.get_location(city, date) {
             Some(loc) => loc.country_code.clone(),
             None => "".to_string(),
         }
     }
 
-    pub fn get_city_from_location(&self, airport: &str) -> String {
-        match self.locations.get(airport) {
-            Some(loc) => loc.city_code_list[0].clone(),
+    /// Some airports serve several cities (EWR serves NYC and EWR, BSL/MLH/EAP serve BSL, MLH and EAP).
+    /// The first of `preferred_cities` served by the airport is returned, falling back to the main city.
+    pub fn get_city_from_location(
+        &self,
+        airport: &str,
+        date: Option<NaiveDate>,
+        preferred_cities: &[&str],
+    ) -> String {
+        match self.get_location(airport, date) {
+            Some(loc) => preferred_cities
+                .iter()
+                .find(|city| loc.city_code_list.iter().any(|c| c == *city))
+                .map(|city| city.to_string())
+                .unwrap_or_else(|| loc.city_code_list[0].clone()),
             None => "".to_string(),
         }
     }
 
+    /// All the cities served by a location, main city first.
+    pub fn get_city_list_from_location(
+        &self,
+        airport: &str,
+        date: Option<NaiveDate>,
+    ) -> Vec<String> {
+        match self.get_location(airport, date) {
+            Some(loc) => loc.city_code_list.clone(),
+            None => vec![],
+        }
+    }
+
+    /// Records of a code valid at `date`. A code can have several at once, like the city and the
+    /// airport records of NCE, so its types are those of all of them.
+    fn get_records(&self, code: &str, date: Option<NaiveDate>) -> Vec<&Location> {
+        self.locations
+            .get(code)
+            .map_or(vec![], |records| records_at(records, date))
+    }
+
+    /// Types of all the records of a code valid at `date`.
+    pub fn get_location_types(&self, code: &str, date: Option<NaiveDate>) -> Vec<LocationType> {
+        let mut location_types = vec![];
+        for loc in self.get_records(code, date) {
+            for location_type in &loc.location_types {
+                if !location_types.contains(location_type) {
+                    location_types.push(*location_type);
+                }
+            }
+        }
+        location_types
+    }
+
+    pub fn is_city(&self, code: &str, date: Option<NaiveDate>) -> bool {
+        self.get_records(code, date)
+            .iter()
+            .any(|loc| loc.location_types.contains(&LocationType::City))
+    }
+
+    pub fn is_airport(&self, code: &str, date: Option<NaiveDate>) -> bool {
+        self.get_records(code, date)
+            .iter()
+            .any(|loc| loc.is_airport())
+    }
+
+    /// Rail stations that are also airports (a few "AR" locations) are not considered rail stations.
+    pub fn is_rail_station(&self, code: &str, date: Option<NaiveDate>) -> bool {
+        let records = self.get_records(code, date);
+        records.iter().any(|loc| loc.is_rail_station())
+            && !records.iter().any(|loc| loc.is_airport())
+    }
+
+    /// Bus stations that are also airports are not considered bus stations.
+    pub fn is_bus_station(&self, code: &str, date: Option<NaiveDate>) -> bool {
+        let records = self.get_records(code, date);
+        records.iter().any(|loc| loc.is_bus_station())
+            && !records.iter().any(|loc| loc.is_airport())
+    }
+
+    pub fn get_distance_between_locations(
+        &self,
+        first_location: &str,
+        second_location: &str,
+        date: Option<NaiveDate>,
+    ) -> Option<Distance> {
+        let (first_lat, first_lng) = self.get_coordinates(first_location, date)?;
+        let (second_lat, second_lng) = self.get_coordinates(second_location, date)?;
+
+        Some(
+            self.distance_model
+                .distance(first_lat, first_lng, second_lat, second_lng),
+        )
+    }
+
+    /// Distance in km, rounded to the nearest km.
     pub fn get_round_distance_between_locations(
         &self,
         first_location: &str,
         second_location: &str,
+        date: Option<NaiveDate>,
     ) -> Option<u64> {
-        let first_airport = self.locations.get(first_location)?;
-        let second_airport = self.locations.get(second_location)?;
+        self.get_distance_between_locations(first_location, second_location, date)
+            .map(|distance| distance.round_km())
+    }
 
-        let first_lat = first_airport.lat?;
-        let first_lng = first_airport.lng?;
-        let second_lat = second_airport.lat?;
-        let second_lng = second_airport.lng?;
+    pub fn get_coordinates(&self, code: &str, date: Option<NaiveDate>) -> Option<(f64, f64)> {
+        let loc = self.get_location(code, date)?;
+        Some((loc.lat?, loc.lng?))
+    }
+
+    /// Locations valid at `date` within `radius_km` of a point and matching `filter`, closest first.
+    fn find_near(
+        &self,
+        lat: f64,
+        lng: f64,
+        radius_km: f64,
+        date: Option<NaiveDate>,
+        filter: impl Fn(&Location) -> bool,
+    ) -> Vec<NearbyLocation> {
+        let mut found: Vec<NearbyLocation> = self
+            .grid
+            .candidates_within(lat, lng, radius_km)
+            .into_iter()
+            .filter_map(|(code, index)| {
+                // the grid holds every record of a code, like the city and the airport records of
+                // NCE: keep those valid at `date`, currently valid without a date
+                let loc = &self.locations[code][*index];
+                let is_valid = match date {
+                    Some(date) => loc.is_valid_at(date),
+                    None => loc.date_until.is_none(),
+                };
+                if !is_valid || !filter(loc) {
+                    return None;
+                }
+                let distance_km = self
+                    .distance_model
+                    .distance(lat, lng, loc.lat?, loc.lng?)
+                    .km();
+                (distance_km <= radius_km).then(|| NearbyLocation {
+                    code: code.clone(),
+                    distance_km,
+                })
+            })
+            .collect();
+
+        found.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+        // a code is found once, at its closest record
+        let mut codes = HashSet::new();
+        found.retain(|nearby| codes.insert(nearby.code.clone()));
+        found
+    }
+
+    /// All airports within `radius_km` of a point, closest first.
+    pub fn find_airports_within(
+        &self,
+        lat: f64,
+        lng: f64,
+        radius_km: f64,
+        date: Option<NaiveDate>,
+    ) -> Vec<NearbyLocation> {
+        self.find_near(lat, lng, radius_km, date, Location::is_airport)
+    }
+
+    /// The `k` nearest commercial airports to a point, closest first.
+    /// Airports are considered commercial when NeoBase ranks them (they have a `page_rank`).
+    pub fn find_nearest_commercial_airports(
+        &self,
+        lat: f64,
+        lng: f64,
+        k: usize,
+        date: Option<NaiveDate>,
+    ) -> Vec<NearbyLocation> {
+        let is_commercial_airport = |loc: &Location| loc.is_airport() && loc.page_rank.is_some();
+
+        // widen the search until enough airports are found
+        let mut radius_km = 100.0;
+        loop {
+            let mut found = self.find_near(lat, lng, radius_km, date, is_commercial_airport);
+            if found.len() >= k || radius_km >= MAX_DISTANCE_KM {
+                found.truncate(k);
+                return found;
+            }
+            radius_km = (radius_km * 2.0).min(MAX_DISTANCE_KM);
+        }
+    }
 
-        let distance = haversine_distance(first_lat, first_lng, second_lat, second_lng);
-        Some(distance)
+    /// Airports within `radius_km` of a city or airport, other than the location itself.
+    /// Run it on both ends of an OnD to get the alternate airports of the OnD.
+    pub fn find_alternate_airports(
+        &self,
+        code: &str,
+        radius_km: f64,
+        date: Option<NaiveDate>,
+    ) -> Vec<NearbyLocation> {
+        match self.get_coordinates(code, date) {
+            Some((lat, lng)) => self
+                .find_airports_within(lat, lng, radius_km, date)
+                .into_iter()
+                .filter(|nearby| nearby.code != code)
+                .collect(),
+            None => vec![],
+        }
     }
 }
 
@@ -136,20 +481,6 @@ impl Default for Locations {
     }
 }
 
-fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> u64 {
-    const R: f64 = 6371.0; // Radius of the Earth in km
-    let d_lat = (lat2 - lat1).to_radians();
-    let d_lon = (lon2 - lon1).to_radians();
-
-    let a = (d_lat / 2.0).sin() * (d_lat / 2.0).sin()
-        + lat1.to_radians().cos()
-            * lat2.to_radians().cos()
-            * (d_lon / 2.0).sin()
-            * (d_lon / 2.0).sin();
-    let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
-    (R * c) as u64
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -158,4 +489,264 @@ mod tests {
     fn test_get_geodata() {
         get_geodata("src/neobase/data.csv");
     }
+
+    const REUSED_CODE_DATA: &str = "\
+iata_code^latitude^longitude^date_from^date_until^country_code^city_code_list
+XXX^10.0^10.0^^2010-12-31^FR^OLD
+XXX^20.0^20.0^2011-01-01^^DE^NEW
+";
+
+    #[test]
+    fn test_reused_code_is_resolved_by_date() {
+        let locations = Locations::from_reader(REUSED_CODE_DATA.as_bytes());
+        let old_date = NaiveDate::from_ymd_opt(2005, 6, 1);
+        let new_date = NaiveDate::from_ymd_opt(2021, 6, 1);
+
+        assert_eq!(
+            locations.get_city_from_location("XXX", old_date, &[]),
+            "OLD"
+        );
+        assert_eq!(locations.get_country_from_city("XXX", old_date), "FR");
+        assert_eq!(
+            locations.get_city_from_location("XXX", new_date, &[]),
+            "NEW"
+        );
+        assert_eq!(locations.get_city_from_location("XXX", None, &[]), "NEW");
+    }
+
+    const MULTI_CITY_DATA: &str = "\
+iata_code^latitude^longitude^country_code^city_code_list
+EWR^40.69^-74.17^US^EWR,NYC
+";
+
+    #[test]
+    fn test_multi_city_airport_prefers_search_city() {
+        let locations = Locations::from_reader(MULTI_CITY_DATA.as_bytes());
+
+        assert_eq!(locations.get_city_from_location("EWR", None, &[]), "EWR");
+        assert_eq!(
+            locations.get_city_from_location("EWR", None, &["PAR", "NYC"]),
+            "NYC"
+        );
+        assert_eq!(
+            locations.get_city_list_from_location("EWR", None),
+            vec!["EWR", "NYC"]
+        );
+    }
+
+    const LOCATION_TYPE_DATA: &str = "\
+iata_code^latitude^longitude^fcode^country_code^city_code_list^location_type
+PAR^48.85^2.35^PPLC^FR^PAR^C
+CDG^49.01^2.55^AIRP^FR^PAR^A
+NCE^43.66^7.22^AIRP^FR^NCE^CA
+XHN^50.84^4.36^RSTN^BE^BRU^R
+";
+
+    #[test]
+    fn test_location_types() {
+        let locations = Locations::from_reader(LOCATION_TYPE_DATA.as_bytes());
+
+        assert!(locations.is_city("PAR", None));
+        assert!(!locations.is_airport("PAR", None));
+        assert!(locations.is_airport("CDG", None));
+        assert!(locations.is_city("NCE", None) && locations.is_airport("NCE", None));
+        assert!(locations.is_rail_station("XHN", None));
+        assert!(!locations.is_rail_station("CDG", None));
+        assert!(!locations.is_bus_station("XHN", None));
+        assert_eq!(
+            locations.get_location_types("NCE", None),
+            vec![LocationType::City, LocationType::Airport]
+        );
+    }
+
+    #[test]
+    fn test_location_types_of_separate_records() {
+        // OPTD has a city record and an airport record for NCE, in either order
+        for data in [
+            "iata_code^fcode^city_code_list^location_type\nNCE^PPLA2^NCE^C\nNCE^AIRP^NCE^A\n",
+            "iata_code^fcode^city_code_list^location_type\nNCE^AIRP^NCE^A\nNCE^PPLA2^NCE^C\n",
+        ] {
+            let locations = Locations::from_reader(data.as_bytes());
+            assert!(locations.is_city("NCE", None));
+            assert!(locations.is_airport("NCE", None));
+            assert!(locations.is_city("NCE", NaiveDate::from_ymd_opt(2021, 6, 1)));
+            assert!(locations.is_airport("NCE", NaiveDate::from_ymd_opt(2021, 6, 1)));
+            let mut location_types = locations.get_location_types("NCE", None);
+            location_types.sort_by_key(|location_type| format!("{location_type:?}"));
+            assert_eq!(
+                location_types,
+                vec![LocationType::Airport, LocationType::City]
+            );
+        }
+
+        // a record that is no longer valid does not count
+        let locations = Locations::from_reader(
+            "iata_code^fcode^date_until^city_code_list^location_type\nXXX^AIRP^2010-12-31^XXX^A\nXXX^RSTN^^XXX^R\n"
+                .as_bytes(),
+        );
+        assert!(locations.is_rail_station("XXX", None));
+        assert!(!locations.is_airport("XXX", NaiveDate::from_ymd_opt(2021, 6, 1)));
+    }
+
+    const SPATIAL_DATA: &str = "\
+iata_code^latitude^longitude^fcode^page_rank^country_code^city_code_list^location_type
+PAR^48.85341^2.3488^PPLC^^FR^PAR^C
+CDG^49.01278^2.55^AIRP^0.46^FR^PAR^A
+ORY^48.72333^2.37944^AIRP^0.24^FR^PAR^A
+BVA^49.45444^2.11278^AIRP^0.05^FR^PAR^A
+LBG^48.96944^2.44139^AIRP^^FR^PAR^A
+LIS^38.7813^-9.13592^AIRP^0.2^PT^LIS^CA
+";
+
+    #[test]
+    fn test_spatial_queries() {
+        let locations = Locations::from_reader(SPATIAL_DATA.as_bytes());
+        let (lat, lng) = locations.get_coordinates("PAR", None).unwrap();
+
+        let codes = |found: Vec<NearbyLocation>| -> Vec<String> {
+            found.into_iter().map(|nearby| nearby.code).collect()
+        };
+
+        assert_eq!(
+            codes(locations.find_airports_within(lat, lng, 30.0, None)),
+            vec!["LBG", "ORY", "CDG"]
+        );
+        assert_eq!(
+            codes(locations.find_nearest_commercial_airports(lat, lng, 3, None)),
+            vec!["ORY", "CDG", "BVA"]
+        );
+        assert_eq!(
+            codes(locations.find_alternate_airports("CDG", 100.0, None)),
+            vec!["LBG", "ORY", "BVA"]
+        );
+        assert_eq!(
+            codes(locations.find_nearest_commercial_airports(lat, lng, 10, None)).len(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_spatial_queries_near_the_equator() {
+        // about 110.0 km north on the ellipsoid, in the next row of cells
+        let locations = Locations::from_reader(
+            "iata_code^latitude^longitude^fcode^city_code_list^location_type\nXXX^1.0^20.0^AIRP^XXX^A\n"
+                .as_bytes(),
+        )
+        .with_distance_model(DistanceModel::Wgs84Geodesic);
+
+        let found = locations.find_airports_within(0.005, 20.0, 110.3, None);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].distance_km < 110.3);
+    }
+
+    #[test]
+    fn test_spatial_queries_with_separate_city_records() {
+        // the airport record of NCE is found whether the city record comes before or after it
+        for data in [
+            "iata_code^latitude^longitude^fcode^page_rank^country_code^city_code_list^location_type\n\
+             NCE^43.70313^7.26608^PPLA2^^FR^NCE^C\n\
+             NCE^43.66272^7.20787^AIRP^0.16^FR^NCE^A\n",
+            "iata_code^latitude^longitude^fcode^page_rank^country_code^city_code_list^location_type\n\
+             NCE^43.66272^7.20787^AIRP^0.16^FR^NCE^A\n\
+             NCE^43.70313^7.26608^PPLA2^^FR^NCE^C\n",
+        ] {
+            let locations = Locations::from_reader(data.as_bytes());
+            let date = NaiveDate::from_ymd_opt(2021, 6, 1);
+            for date in [None, date] {
+                let found = locations.find_airports_within(43.7, 7.25, 30.0, date);
+                assert_eq!(found.len(), 1);
+                assert_eq!(found[0].code, "NCE");
+                assert!(found[0].distance_km > 3.0);
+                let found = locations.find_nearest_commercial_airports(43.7, 7.25, 1, date);
+                assert_eq!(found[0].code, "NCE");
+            }
+        }
+    }
+
+    const FULL_RECORD_DATA: &str = "\
+iata_code^icao_code^faa_code^geoname_id^name^asciiname^latitude^longitude^fcode^page_rank^country_code^country_name^continent_name^population^elevation^timezone^city_code_list^city_name_list^location_type^alt_name_section^unlc_list
+CDG^LFPG^^6269554^Paris Charles de Gaulle Airport^Paris Charles de Gaulle Airport^49.01278^2.55^AIRP^0.46^FR^France^Europe^0^119^Europe/Paris^PAR^Paris^A^de|Flughafen Paris-Charles-de-Gaulle|=en|Roissy Airport|^FRCDG|
+ORY^LFPO^^2988500^Paris Orly Airport^Paris Orly Airport^48.72333^2.37944^AIRP^0.24^FR^France^Europe^^89^Europe/Paris^PAR^Paris^A^en|Orly|^FRORY|
+JFK^KJFK^JFK^5122732^John F Kennedy International Airport^John F Kennedy International Airport^40.63980^-73.77869^AIRP^0.59^US^United States^North America^^4^America/New_York^NYC^New York City^A^^USJFK|
+";
+
+    #[test]
+    fn test_full_record_access() {
+        let locations = Locations::from_reader(FULL_RECORD_DATA.as_bytes());
+        let cdg = locations.get_location("CDG", None).unwrap();
+
+        assert_eq!(cdg.icao_code.as_deref(), Some("LFPG"));
+        assert_eq!(cdg.faa_code, None);
+        assert_eq!(cdg.country_name, "France");
+        assert_eq!(cdg.continent_name, "Europe");
+        assert_eq!(cdg.timezone, "Europe/Paris");
+        assert_eq!(cdg.elevation, Some(119));
+        assert_eq!(cdg.unlocode_list, vec!["FRCDG"]);
+        assert_eq!(cdg.alt_names[1].name, "Roissy Airport");
+    }
+
+    #[test]
+    fn test_lookup_by_code_type_and_name() {
+        let locations = Locations::from_reader(FULL_RECORD_DATA.as_bytes());
+        let iata_code = |loc: Option<&Location>| loc.map(|loc| loc.iata_code.clone());
+
+        assert_eq!(
+            iata_code(locations.get_location_by_code(CodeType::Icao, "LFPO", None)),
+            Some("ORY".to_string())
+        );
+        assert_eq!(
+            iata_code(locations.get_location_by_code(CodeType::Faa, "JFK", None)),
+            Some("JFK".to_string())
+        );
+        assert_eq!(
+            iata_code(locations.get_location_by_code(CodeType::Unlocode, "FRCDG", None)),
+            Some("CDG".to_string())
+        );
+        assert_eq!(
+            iata_code(locations.get_location_by_code(CodeType::Geonames, "5122732", None)),
+            Some("JFK".to_string())
+        );
+
+        let found: Vec<&str> = locations
+            .search_by_name("PARIS", None)
+            .iter()
+            .map(|loc| loc.iata_code.as_str())
+            .collect();
+        assert_eq!(found, vec!["CDG", "ORY"]);
+        assert_eq!(locations.search_by_name("roissy", None).len(), 1);
+    }
+
+    #[test]
+    fn test_search_by_name_of_separate_records() {
+        // the city record of NCE comes last, so it is the one its code resolves to
+        let locations = Locations::from_reader(
+            "iata_code^name^fcode^page_rank^date_until^city_code_list^location_type
+NCE^Nice Côte d'Azur International Airport^AIRP^0.16^^NCE^A
+NCE^Nice^PPLA2^0.2^^NCE^C
+XXX^Old Nice Airfield^AIRP^^2010-12-31^NCE^A
+XXX^Nice Heliport^AIRP^^^NCE^A
+"
+            .as_bytes(),
+        );
+        let found = |query: &str, date: Option<NaiveDate>| -> Vec<String> {
+            locations
+                .search_by_name(query, date)
+                .iter()
+                .map(|loc| loc.name.clone())
+                .collect()
+        };
+
+        assert_eq!(locations.get_location("NCE", None).unwrap().name, "Nice");
+        assert_eq!(
+            found("côte d'azur", None),
+            vec!["Nice Côte d'Azur International Airport"]
+        );
+        // one location per code
+        assert_eq!(found("nice", None), vec!["Nice", "Nice Heliport"]);
+        assert_eq!(found("airfield", None), Vec::<String>::new());
+        assert_eq!(
+            found("airfield", NaiveDate::from_ymd_opt(2005, 1, 1)),
+            vec!["Old Nice Airfield"]
+        );
+    }
 }
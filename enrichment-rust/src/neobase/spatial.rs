@@ -0,0 +1,74 @@
+use std::collections::{BTreeSet, HashMap};
+
+const CELL_SIZE_DEG: f64 = 1.0;
+/// Shortest degree of latitude, at the equator on the WGS-84 ellipsoid, so that the candidate box
+/// covers the radius with both distance models.
+const KM_PER_DEG_LAT: f64 = 110.57;
+
+type Cell = (i32, i32);
+
+/// Fixed-size lat/lng grid over the locations, each cell holding the records located in it.
+/// Entries are `(code, index of the record in the code's record list)`.
+pub(super) struct SpatialGrid {
+    cells: HashMap<Cell, Vec<(String, usize)>>,
+}
+
+fn cell_of(lat: f64, lng: f64) -> Cell {
+    (
+        (lat / CELL_SIZE_DEG).floor() as i32,
+        (lng / CELL_SIZE_DEG).floor() as i32,
+    )
+}
+
+/// Wraps a column index around the antimeridian.
+fn normalize_column(column: i32) -> i32 {
+    let columns = (360.0 / CELL_SIZE_DEG) as i32;
+    let half = columns / 2;
+    (column + half).rem_euclid(columns) - half
+}
+
+impl SpatialGrid {
+    pub(super) fn new() -> Self {
+        SpatialGrid {
+            cells: HashMap::new(),
+        }
+    }
+
+    pub(super) fn insert(&mut self, code: &str, record_index: usize, lat: f64, lng: f64) {
+        self.cells
+            .entry(cell_of(lat, lng))
+            .or_default()
+            .push((code.to_string(), record_index));
+    }
+
+    /// Entries of every cell intersecting the bounding box of the circle.
+    /// Some of them may be farther than `radius_km`: callers still have to check the distance.
+    pub(super) fn candidates_within(
+        &self,
+        lat: f64,
+        lng: f64,
+        radius_km: f64,
+    ) -> Vec<&(String, usize)> {
+        let d_lat = radius_km / KM_PER_DEG_LAT;
+        let lat_min = (lat - d_lat).max(-90.0);
+        let lat_max = (lat + d_lat).min(90.0);
+
+        // a degree of longitude shrinks with the latitude, and the circle may contain a pole
+        let widest_lat = lat_min.abs().max(lat_max.abs());
+        let d_lng = if widest_lat >= 89.0 {
+            180.0
+        } else {
+            (d_lat / widest_lat.to_radians().cos()).min(180.0)
+        };
+
+        let (row_min, col_min) = cell_of(lat_min, lng - d_lng);
+        let (row_max, col_max) = cell_of(lat_max, lng + d_lng);
+        let columns: BTreeSet<i32> = (col_min..=col_max).map(normalize_column).collect();
+
+        (row_min..=row_max)
+            .flat_map(|row| columns.iter().map(move |column| (row, *column)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .collect()
+    }
+}
@@ -0,0 +1,177 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use arc_swap::ArcSwap;
+
+use crate::{
+    airlines,
+    currency_exchange::{self, Currency, ExchangeRatesError},
+    emissions,
+    neobase::{self, NeobaseError},
+};
+
+pub mod reload;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReferenceDataError {
+    #[error("Invalid NeoBase file: {0}")]
+    Neobase(#[from] NeobaseError),
+    #[error("Invalid exchange rates file: {0}")]
+    ExchangeRates(#[from] ExchangeRatesError),
+    #[error("No location in the NeoBase file")]
+    NoLocations,
+    #[error("No rates for {0:?} in the new exchange rates file")]
+    MissingCurrencies(Vec<Currency>),
+    #[error("Only {nb_of_locations} locations in the new NeoBase file, instead of {nb_of_current_locations}")]
+    TooFewLocations {
+        nb_of_locations: usize,
+        nb_of_current_locations: usize,
+    },
+}
+
+/// Share of the current locations a reloaded NeoBase file must have at least, so that a
+/// truncated file is not swapped in.
+const MIN_RELOADED_LOCATIONS_RATIO: f64 = 0.9;
+
+/// Files of the reference data updated over time: NeoBase and the ECB rates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReferenceDataPaths {
+    pub neobase: PathBuf,
+    pub exchange_rates: PathBuf,
+}
+
+impl Default for ReferenceDataPaths {
+    fn default() -> Self {
+        ReferenceDataPaths {
+            neobase: PathBuf::from("src/neobase/data.csv"),
+            exchange_rates: PathBuf::from("src/currency_exchange/eurofxref.csv"),
+        }
+    }
+}
+
+/// The reference data searches are enriched with, loaded once and shared between threads.
+#[derive(Clone)]
+pub struct ReferenceData {
+    pub locations: Arc<neobase::Locations>,
+    pub exchange_rates: Arc<currency_exchange::ExchangeRates>,
+    pub emissions_model: Arc<emissions::EmissionsModel>,
+    pub airlines: Arc<airlines::Airlines>,
+}
+
+impl ReferenceData {
+    /// Loads the reference files of the crate.
+    pub fn load() -> Self {
+        ReferenceData::try_load(&ReferenceDataPaths::default())
+            .expect("Failed to load reference data")
+    }
+
+    /// Loads NeoBase and the rates from `paths`, and the other reference files of the crate.
+    pub fn try_load(paths: &ReferenceDataPaths) -> Result<Self, ReferenceDataError> {
+        let (locations, exchange_rates) = load_updated(paths, None)?;
+        Ok(ReferenceData {
+            locations: Arc::new(locations),
+            exchange_rates: Arc::new(exchange_rates),
+            emissions_model: Arc::new(emissions::EmissionsModel::new()),
+            airlines: Arc::new(airlines::Airlines::new()),
+        })
+    }
+
+    pub fn new(
+        locations: neobase::Locations,
+        exchange_rates: currency_exchange::ExchangeRates,
+        emissions_model: emissions::EmissionsModel,
+        airlines: airlines::Airlines,
+    ) -> Self {
+        ReferenceData {
+            locations: Arc::new(locations),
+            exchange_rates: Arc::new(exchange_rates),
+            emissions_model: Arc::new(emissions_model),
+            airlines: Arc::new(airlines),
+        }
+    }
+}
+
+/// Loads and validates the files updated over time. When reloading them, they must not lose
+/// currencies or many locations of the `current` data.
+fn load_updated(
+    paths: &ReferenceDataPaths,
+    current: Option<&ReferenceData>,
+) -> Result<(neobase::Locations, currency_exchange::ExchangeRates), ReferenceDataError> {
+    let locations = neobase::Locations::try_from_file(&paths.neobase)?;
+    if locations.is_empty() {
+        return Err(ReferenceDataError::NoLocations);
+    }
+    let exchange_rates = currency_exchange::ExchangeRates::from_file(&paths.exchange_rates)?;
+
+    if let Some(current) = current {
+        let nb_of_current_locations = current.locations.len();
+        if (locations.len() as f64) < nb_of_current_locations as f64 * MIN_RELOADED_LOCATIONS_RATIO
+        {
+            return Err(ReferenceDataError::TooFewLocations {
+                nb_of_locations: locations.len(),
+                nb_of_current_locations,
+            });
+        }
+        let mut missing_currencies: Vec<Currency> = current
+            .exchange_rates
+            .currencies()
+            .filter(|currency| exchange_rates.get_rate(currency).is_none())
+            .copied()
+            .collect();
+        if !missing_currencies.is_empty() {
+            missing_currencies.sort_by_key(|currency| format!("{currency:?}"));
+            return Err(ReferenceDataError::MissingCurrencies(missing_currencies));
+        }
+    }
+    Ok((locations, exchange_rates))
+}
+
+/// Reference data that can be reloaded while it is used. Each enrichment should take a
+/// `snapshot` first, so that it uses consistent data even when a reload happens meanwhile.
+pub struct ReferenceDataHandle {
+    current: ArcSwap<ReferenceData>,
+    paths: ReferenceDataPaths,
+    /// Reloads one at a time.
+    reloading: Mutex<()>,
+}
+
+impl ReferenceDataHandle {
+    pub fn new(reference_data: ReferenceData, paths: ReferenceDataPaths) -> Self {
+        ReferenceDataHandle {
+            current: ArcSwap::from_pointee(reference_data),
+            paths,
+            reloading: Mutex::new(()),
+        }
+    }
+
+    pub fn load(paths: ReferenceDataPaths) -> Result<Self, ReferenceDataError> {
+        Ok(Self::new(ReferenceData::try_load(&paths)?, paths))
+    }
+
+    pub fn paths(&self) -> &ReferenceDataPaths {
+        &self.paths
+    }
+
+    /// The current reference data, unchanged by later reloads.
+    pub fn snapshot(&self) -> Arc<ReferenceData> {
+        self.current.load_full()
+    }
+
+    /// Reloads NeoBase and the rates from their files, and swaps them in if they are valid.
+    /// Otherwise, the current data is kept.
+    pub fn reload(&self) -> Result<(), ReferenceDataError> {
+        let _reloading = self
+            .reloading
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        let current = self.snapshot();
+        let (locations, exchange_rates) = load_updated(&self.paths, Some(&current))?;
+        self.current.store(Arc::new(ReferenceData {
+            locations: Arc::new(locations),
+            exchange_rates: Arc::new(exchange_rates),
+            emissions_model: current.emissions_model.clone(),
+            airlines: current.airlines.clone(),
+        }));
+        Ok(())
+    }
+}
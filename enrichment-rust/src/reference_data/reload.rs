@@ -0,0 +1,215 @@
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+
+use super::{ReferenceDataError, ReferenceDataHandle};
+
+/// How often the reloader checks its triggers.
+const TICK: Duration = Duration::from_secs(1);
+
+/// What reloads the reference data.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReloadTriggers {
+    /// A change of the modification time of a file, once it has not changed for a tick, so that
+    /// files being written are not loaded.
+    pub file_changes: bool,
+    /// A SIGHUP signal.
+    pub sighup: bool,
+    /// A schedule.
+    pub every: Option<Duration>,
+}
+
+/// Checks the reload triggers of a handle.
+pub struct Reloader {
+    handle: Arc<ReferenceDataHandle>,
+    triggers: ReloadTriggers,
+    sighup: Arc<AtomicBool>,
+    /// Modification times of the files when they were last loaded, and at the last tick.
+    loaded_modified: Vec<Option<SystemTime>>,
+    last_modified: Vec<Option<SystemTime>>,
+    last_reload: Instant,
+}
+
+impl Reloader {
+    /// Registers the SIGHUP handler, if triggered by SIGHUP.
+    pub fn new(
+        handle: Arc<ReferenceDataHandle>,
+        triggers: ReloadTriggers,
+    ) -> Result<Self, std::io::Error> {
+        let sighup = Arc::new(AtomicBool::new(false));
+        if triggers.sighup {
+            signal_hook::flag::register(signal_hook::consts::SIGHUP, sighup.clone())?;
+        }
+        let modified = modified(&handle);
+        Ok(Reloader {
+            handle,
+            triggers,
+            sighup,
+            loaded_modified: modified.clone(),
+            last_modified: modified,
+            last_reload: Instant::now(),
+        })
+    }
+
+    /// Reloads if a trigger fired since the last tick. `None` when nothing fired.
+    pub fn tick(&mut self) -> Option<Result<(), ReferenceDataError>> {
+        let modified = modified(&self.handle);
+        let files_changed = self.triggers.file_changes
+            && modified != self.loaded_modified
+            && modified == self.last_modified;
+        self.last_modified = modified.clone();
+        let sighup = self.sighup.swap(false, Ordering::Relaxed);
+        let scheduled = self
+            .triggers
+            .every
+            .is_some_and(|every| self.last_reload.elapsed() >= every);
+        if !(files_changed || sighup || scheduled) {
+            return None;
+        }
+
+        // a failed reload waits for the files to change again
+        self.loaded_modified = modified;
+        self.last_reload = Instant::now();
+        Some(self.handle.reload())
+    }
+
+    /// Checks the triggers every second, in a background thread, and logs the reloads.
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(TICK);
+            match self.tick() {
+                None => {}
+                Some(Ok(())) => eprintln!("Reloaded reference data"),
+                Some(Err(error)) => {
+                    eprintln!("Kept the current reference data, failed to reload: {error}")
+                }
+            }
+        })
+    }
+}
+
+fn modified(handle: &ReferenceDataHandle) -> Vec<Option<SystemTime>> {
+    let paths = handle.paths();
+    [&paths.neobase, &paths.exchange_rates]
+        .iter()
+        .map(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::currency_exchange::Currency;
+    use crate::reference_data::ReferenceDataPaths;
+
+    const NEOBASE: &str = "\
+iata_code^latitude^longitude^fcode^page_rank^country_code^city_code_list^location_type
+PAR^48.85341^2.3488^PPLC^^FR^PAR^C
+LIS^38.7813^-9.13592^AIRP^0.2^PT^LIS^CA
+";
+    const RATES: &str = "Date, USD\n19 November 2021, 1.1271\n";
+
+    fn write(path: &PathBuf, content: &str, modified: u64) {
+        fs::write(path, content).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reload() {
+        let directory = std::env::temp_dir().join(format!("reload-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let paths = ReferenceDataPaths {
+            neobase: directory.join("neobase.csv"),
+            exchange_rates: directory.join("eurofxref.csv"),
+        };
+        write(&paths.neobase, NEOBASE, 1);
+        write(&paths.exchange_rates, RATES, 1);
+        let handle = Arc::new(ReferenceDataHandle::load(paths.clone()).unwrap());
+        let usd = |handle: &ReferenceDataHandle| {
+            handle.snapshot().exchange_rates.get_rate(&Currency::USD)
+        };
+
+        let mut reloader = Reloader::new(
+            handle.clone(),
+            ReloadTriggers {
+                file_changes: true,
+                ..ReloadTriggers::default()
+            },
+        )
+        .unwrap();
+        assert!(reloader.tick().is_none());
+
+        // reloads once the file stops changing
+        let snapshot = handle.snapshot();
+        write(
+            &paths.exchange_rates,
+            "Date, USD\n20 November 2021, 1.2\n",
+            2,
+        );
+        assert!(reloader.tick().is_none());
+        assert!(reloader.tick().unwrap().is_ok());
+        assert_eq!(usd(&handle), Some(1.2));
+        assert_eq!(
+            snapshot.exchange_rates.get_rate(&Currency::USD),
+            Some(1.1271)
+        );
+        assert!(reloader.tick().is_none());
+
+        // invalid files are not swapped in
+        write(
+            &paths.exchange_rates,
+            "Date, USD\n21 November 2021, N/A\n",
+            3,
+        );
+        reloader.tick();
+        assert!(reloader.tick().unwrap().is_err());
+        assert_eq!(usd(&handle), Some(1.2));
+        write(&paths.neobase, "iata_code^latitude\n", 4);
+        write(&paths.exchange_rates, RATES, 4);
+        reloader.tick();
+        assert!(reloader.tick().unwrap().is_err());
+        assert_eq!(usd(&handle), Some(1.2));
+        assert_eq!(handle.snapshot().locations.len(), 2);
+
+        // nor files losing currencies or many locations
+        write(&paths.neobase, NEOBASE, 5);
+        write(
+            &paths.exchange_rates,
+            "Date, GBP\n22 November 2021, 0.84\n",
+            5,
+        );
+        reloader.tick();
+        assert!(matches!(
+            reloader.tick().unwrap(),
+            Err(ReferenceDataError::MissingCurrencies(currencies)) if currencies == [Currency::USD]
+        ));
+        write(&paths.neobase, &NEOBASE[..NEOBASE.find("LIS").unwrap()], 6);
+        write(&paths.exchange_rates, RATES, 6);
+        reloader.tick();
+        assert!(matches!(
+            reloader.tick().unwrap(),
+            Err(ReferenceDataError::TooFewLocations {
+                nb_of_locations: 1,
+                nb_of_current_locations: 2,
+            })
+        ));
+        assert_eq!(usd(&handle), Some(1.2));
+        assert_eq!(handle.snapshot().locations.len(), 2);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use super::{flight::Flight, reco::Reco};
+
+/// 64-bit FNV-1a: unlike `DefaultHasher`, its output does not change between Rust versions,
+/// so fingerprints can be stored and compared across runs.
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    bytes.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+fn flight_key(flight: &Flight) -> String {
+    let date = |date: Option<chrono::NaiveDate>| date.map(|d| d.to_string()).unwrap_or_default();
+    let time = |time: Option<chrono::NaiveTime>| {
+        time.map(|t| t.format("%H:%M").to_string())
+            .unwrap_or_default()
+    };
+    format!(
+        "{}|{}|{}|{}|{}|{}|{}|{}",
+        flight.dep_airport,
+        date(flight.dep_date),
+        time(flight.dep_time),
+        flight.arr_airport,
+        date(flight.arr_date),
+        time(flight.arr_time),
+        flight.marketing_airline,
+        flight.flight_nb,
+    )
+}
+
+/// Identifies the itinerary of a reco (airports, dates, times and flight numbers of its flights),
+/// whatever its fare. Two recos with the same fingerprint fly the exact same flights.
+pub fn itinerary_fingerprint(reco: &Reco) -> String {
+    let key = reco
+        .flights
+        .iter()
+        .map(flight_key)
+        .collect::<Vec<String>>()
+        .join(";");
+    format!("{:016x}", fnv1a_64(key.as_bytes()))
+}
+
+/// For each `(fingerprint, price)`, whether it duplicates another one: the cheapest reco of an
+/// itinerary is kept (the first one on ties), all the others are duplicates.
+pub fn find_duplicates(recos: &[(&str, f64)]) -> Vec<bool> {
+    let mut cheapest: HashMap<&str, usize> = HashMap::new();
+    for (index, (fingerprint, price)) in recos.iter().enumerate() {
+        cheapest
+            .entry(fingerprint)
+            .and_modify(|kept| {
+                if *price < recos[*kept].1 {
+                    *kept = index;
+                }
+            })
+            .or_insert(index);
+    }
+
+    recos
+        .iter()
+        .enumerate()
+        .map(|(index, (fingerprint, _))| cheapest[fingerprint] != index)
+        .collect()
+}
@@ -1,18 +1,38 @@
+use chrono::{NaiveDate, NaiveDateTime};
 use serde::Serialize;
 
-use crate::neobase;
+use crate::{cabin::Cabin, emissions, neobase, serde_json_helpers::serialize_f64_2_decimals};
 
-use super::{flight::Flight, typedefs::CityCode};
+use super::{
+    flight::Flight,
+    typedefs::{AirportCode, CityCode},
+};
 
 #[derive(Serialize)]
 pub struct EnrichedFlight {
     // Enriched
+    pub dep_airport: AirportCode, // overriden (same value)
+    pub arr_airport: AirportCode, // overriden (same value)
+    #[serde(skip)]
+    pub dep_date: Option<NaiveDate>,
+    #[serde(skip)]
+    pub dep_datetime: Option<NaiveDateTime>,
+    #[serde(skip)]
+    pub arr_datetime: Option<NaiveDateTime>,
     pub dep_city: CityCode,
     pub arr_city: CityCode,
+    pub dep_city_list: Vec<CityCode>,
+    pub arr_city_list: Vec<CityCode>,
     pub distance: u64,
+    pub is_rail: bool,
+    pub is_bus: bool,
+    #[serde(serialize_with = "serialize_f64_2_decimals")]
+    pub co2_kg: f64,
     pub marketing_airline: String, // overriden (same value)
+    pub flight_nb: String,         // overriden (same value)
     pub operating_airline: String, // overriden
     pub cabin: String,             // overriden (same value)
+    pub cabin_class: Option<Cabin>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -28,29 +48,79 @@ impl EnrichedFlight {
     pub fn enrich_from(
         flight: &Flight,
         neobase_locations: &neobase::Locations,
+        emissions_model: &emissions::EmissionsModel,
+        search_cities: &[&str],
     ) -> Result<EnrichedFlight, EnrichFlightError> {
-        let dep_city = neobase_locations.get_city_from_location(&flight.dep_airport);
-        let arr_city = neobase_locations.get_city_from_location(&flight.arr_airport);
+        // codes are resolved as of the flight's departure, as IATA codes get reused over time.
+        // For multi-city airports, the cities of the search are preferred so that OnD matching works.
+        let dep_city = neobase_locations.get_city_from_location(
+            &flight.dep_airport,
+            flight.dep_date,
+            search_cities,
+        );
+        let arr_city = neobase_locations.get_city_from_location(
+            &flight.arr_airport,
+            flight.dep_date,
+            search_cities,
+        );
+        let dep_city_list =
+            neobase_locations.get_city_list_from_location(&flight.dep_airport, flight.dep_date);
+        let arr_city_list =
+            neobase_locations.get_city_list_from_location(&flight.arr_airport, flight.dep_date);
 
         let distance = neobase_locations
-            .get_round_distance_between_locations(&flight.dep_airport, &flight.arr_airport)
+            .get_round_distance_between_locations(
+                &flight.dep_airport,
+                &flight.arr_airport,
+                flight.dep_date,
+            )
             .ok_or(EnrichFlightError::MissingLocationInDistanceCalculation {
-                dep_airport: flight.dep_airport.clone(),
-                arr_airport: flight.arr_airport.clone(),
+                dep_airport: flight.dep_airport.to_string(),
+                arr_airport: flight.arr_airport.to_string(),
             })?;
 
+        // a segment is a surface one as soon as one of its ends is a rail or bus station
+        let is_rail = neobase_locations.is_rail_station(&flight.dep_airport, flight.dep_date)
+            || neobase_locations.is_rail_station(&flight.arr_airport, flight.dep_date);
+        let is_bus = neobase_locations.is_bus_station(&flight.dep_airport, flight.dep_date)
+            || neobase_locations.is_bus_station(&flight.arr_airport, flight.dep_date);
+
+        let cabin_class = Cabin::from_code(&flight.cabin);
+
+        // per passenger, surface segments are not covered by the aviation model
+        let co2_kg = if is_rail || is_bus {
+            0.0
+        } else {
+            emissions_model.co2_kg_per_pax(distance, cabin_class)
+        };
+
+        // as in the Python enricher, an empty operating airline means the marketing one operates
         let operating_airline = flight
             .operating_airline
-            .clone()
-            .unwrap_or(flight.marketing_airline.clone());
+            .as_deref()
+            .filter(|airline| !airline.is_empty())
+            .unwrap_or(&flight.marketing_airline)
+            .to_string();
 
         Ok(EnrichedFlight {
+            dep_airport: flight.dep_airport.to_string(),
+            arr_airport: flight.arr_airport.to_string(),
+            dep_date: flight.dep_date,
+            dep_datetime: flight.dep_datetime(),
+            arr_datetime: flight.arr_datetime(),
             dep_city,
             arr_city,
+            dep_city_list,
+            arr_city_list,
             distance,
-            marketing_airline: flight.marketing_airline.clone(),
+            is_rail,
+            is_bus,
+            co2_kg,
+            marketing_airline: flight.marketing_airline.to_string(),
+            flight_nb: flight.flight_nb.to_string(),
             operating_airline,
-            cabin: flight.cabin.clone(),
+            cabin: flight.cabin.to_string(),
+            cabin_class,
         })
     }
 }
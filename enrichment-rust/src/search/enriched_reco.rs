@@ -1,10 +1,21 @@
+use std::collections::HashSet;
+
 use serde::Serialize;
 
-use crate::{currency_exchange, neobase, serde_json_helpers::serialize_f64_2_decimals};
+use crate::{
+    airlines,
+    cabin::Cabin,
+    currency_exchange, emissions, neobase,
+    serde_json_helpers::{serialize_f64_2_decimals, serialize_vec_f64_2_decimals},
+};
 
 use super::{
+    duplicates::itinerary_fingerprint,
     enriched_flight::{EnrichFlightError, EnrichedFlight},
+    enriched_search::TripType,
     reco::Reco,
+    routing::{circuity, detect_trip_type, flight_backtracks, split_into_bounds},
+    Search,
 };
 
 #[derive(Serialize)]
@@ -18,9 +29,34 @@ pub struct EnrichedReco {
     pub fees_eur: f64,
     pub flights: Vec<EnrichedFlight>, // overriden
     pub flown_distance: u64,
+    #[serde(serialize_with = "serialize_f64_2_decimals")]
+    pub co2_kg: f64,
     pub main_marketing_airline: String,
     pub main_operating_airline: String,
+    pub main_airline_alliance: Option<airlines::Alliance>,
+    pub is_lcc: bool,
+    pub is_codeshare: bool,
+    pub is_interline: bool,
     pub main_cabin: String,
+    /// Normalized cabin of `main_cabin`, so that J and C fares are both business.
+    pub main_cabin_class: Option<Cabin>,
+    pub highest_cabin: Option<Cabin>,
+    pub lowest_cabin: Option<Cabin>,
+    pub mixed_cabin: bool,
+    pub has_surface_segment: bool,
+    #[serde(serialize_with = "serialize_f64_2_decimals")]
+    pub circuity: f64,
+    #[serde(serialize_with = "serialize_vec_f64_2_decimals")]
+    pub bound_circuities: Vec<f64>,
+    pub detour_km: i64,
+    pub has_backtracking: bool,
+    pub trip_type: TripType,
+    pub is_direct: bool,
+    /// 1 for the cheapest recos of the search. Set by the search once all its recos are enriched.
+    pub price_rank: u64,
+    pub itinerary_fingerprint: String,
+    /// A cheaper reco of the search flies the same itinerary. Set by the search too.
+    pub is_duplicate: bool,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -29,24 +65,45 @@ pub enum EnrichRecoError {
     EnrichFlight(#[source] EnrichFlightError),
     #[error("There are no flights in the recommendation.")]
     NoFlightInReco,
+    #[error("Converting the price to euros failed: {0:?}")]
+    ExchangeRate(#[source] currency_exchange::ExchangeRatesError),
 }
 
 impl EnrichedReco {
     pub fn enrich_from(
         reco: &Reco,
+        search: &Search,
+        ond_distance: u64,
         neobase_locations: &neobase::Locations,
         exchange_rates: &currency_exchange::ExchangeRates,
-        currency: &currency_exchange::Currency,
+        emissions_model: &emissions::EmissionsModel,
+        airlines: &airlines::Airlines,
     ) -> Result<EnrichedReco, EnrichRecoError> {
-        let price_eur = exchange_rates.to_euros(reco.price, currency);
-        let taxes_eur = exchange_rates.to_euros(reco.taxes, currency);
-        let fees_eur = exchange_rates.to_euros(reco.fees, currency);
+        let to_euros = |amount| {
+            exchange_rates
+                .to_euros(amount, &search.currency)
+                .map_err(EnrichRecoError::ExchangeRate)
+        };
+        let price_eur = to_euros(reco.price)?;
+        let taxes_eur = to_euros(reco.taxes)?;
+        let fees_eur = to_euros(reco.fees)?;
 
+        let search_cities = [
+            search.origin_city.as_ref(),
+            search.destination_city.as_ref(),
+        ];
         let flights: Vec<EnrichedFlight> = reco
             .flights
             .iter()
             // TODO : avoid cloning ?
-            .map(|flight| EnrichedFlight::enrich_from(flight, neobase_locations))
+            .map(|flight| {
+                EnrichedFlight::enrich_from(
+                    flight,
+                    neobase_locations,
+                    emissions_model,
+                    &search_cities,
+                )
+            })
             .collect::<Result<Vec<EnrichedFlight>, EnrichFlightError>>()
             .map_err(EnrichRecoError::EnrichFlight)?;
 
@@ -55,6 +112,7 @@ impl EnrichedReco {
         }
 
         let flown_distance: u64 = flights.iter().map(|flight| flight.distance).sum();
+        let co2_kg: f64 = flights.iter().map(|flight| flight.co2_kg).sum();
         let main_marketing_airline = flights
             .iter()
             .max_by_key(|flight| flight.distance)
@@ -67,12 +125,85 @@ impl EnrichedReco {
             .unwrap() // safe to unwrap because we know there is at least one flight
             .operating_airline
             .clone();
-        let main_cabin = flights
+        let main_flight = flights.iter().max_by_key(|flight| flight.distance).unwrap(); // safe to unwrap because we know there is at least one flight
+        let main_cabin = main_flight.cabin.clone();
+        let main_cabin_class = main_flight.cabin_class;
+
+        // flights with an unknown cabin are ignored
+        let cabins: HashSet<Cabin> = flights
             .iter()
-            .max_by_key(|flight| flight.distance)
-            .unwrap() // safe to unwrap because we know there is at least one flight
-            .cabin
-            .clone();
+            .filter_map(|flight| flight.cabin_class)
+            .collect();
+        let highest_cabin = cabins.iter().max().copied();
+        let lowest_cabin = cabins.iter().min().copied();
+        let mixed_cabin = cabins.len() > 1;
+
+        let main_airline_alliance = airlines.get_alliance(&main_marketing_airline);
+        let is_lcc = airlines.is_lcc(&main_marketing_airline);
+        let is_codeshare = flights
+            .iter()
+            .any(|flight| flight.marketing_airline != flight.operating_airline);
+        // airlines outside of any alliance only share tickets with themselves
+        let interline_groups: HashSet<Result<airlines::Alliance, &str>> = flights
+            .iter()
+            .map(|flight| {
+                airlines
+                    .get_alliance(&flight.marketing_airline)
+                    .ok_or(flight.marketing_airline.as_str())
+            })
+            .collect();
+        let is_interline = interline_groups.len() > 1;
+
+        // intermodal itineraries (rail/bus segments) skew the air price benchmark
+        let has_surface_segment = flights.iter().any(|flight| flight.is_rail || flight.is_bus);
+
+        // circuity is computed per bound: the outbound goes to the destination, the inbound back home
+        let bounds = split_into_bounds(&flights, &search.destination_city);
+        let bound_destinations = [&search.destination_city, &search.origin_city];
+        // each bound is compared with the distance between its own end cities, so that open jaws
+        // are not measured against the OnD
+        let bound_distances: Vec<(u64, u64)> = bounds
+            .iter()
+            .map(|bound| {
+                let (first, last) = (&bound[0], &bound[bound.len() - 1]);
+                let direct_distance = neobase_locations
+                    .get_round_distance_between_locations(
+                        &first.dep_city,
+                        &last.arr_city,
+                        first.dep_date,
+                    )
+                    .unwrap_or(ond_distance);
+                (
+                    bound.iter().map(|flight| flight.distance).sum(),
+                    direct_distance,
+                )
+            })
+            .collect();
+        let bound_circuities = bound_distances
+            .iter()
+            .map(|(bound_distance, direct_distance)| circuity(*bound_distance, *direct_distance))
+            .collect();
+        let direct_distance: u64 = bound_distances
+            .iter()
+            .map(|(_, direct_distance)| direct_distance)
+            .sum();
+        let circuity = circuity(flown_distance, direct_distance);
+        let detour_km = flown_distance as i64 - direct_distance as i64;
+        let has_backtracking =
+            bounds
+                .iter()
+                .zip(bound_destinations)
+                .any(|(bound, bound_destination)| {
+                    bound.iter().any(|flight| {
+                        flight_backtracks(flight, bound_destination, neobase_locations)
+                    })
+                });
+
+        // direct: no connection on any bound
+        let is_direct = bounds.iter().all(|bound| bound.len() == 1);
+
+        // the search trip type only reflects the request, this one is what is actually flown
+        let trip_type = detect_trip_type(&flights, &search.destination_city);
 
         Ok(EnrichedReco {
             flights,
@@ -80,9 +211,28 @@ impl EnrichedReco {
             taxes_eur,
             fees_eur,
             flown_distance,
+            co2_kg,
             main_marketing_airline,
             main_operating_airline,
+            main_airline_alliance,
+            is_lcc,
+            is_codeshare,
+            is_interline,
             main_cabin,
+            main_cabin_class,
+            highest_cabin,
+            lowest_cabin,
+            mixed_cabin,
+            has_surface_segment,
+            circuity,
+            bound_circuities,
+            detour_km,
+            has_backtracking,
+            trip_type,
+            is_direct,
+            price_rank: 0,
+            itinerary_fingerprint: itinerary_fingerprint(reco),
+            is_duplicate: false,
         })
     }
 }
@@ -1,10 +1,17 @@
+use std::collections::HashSet;
+
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    currency_exchange, neobase, serde_json_helpers::serialize_u64_optional_none_as_minus_one,
+    airlines, currency_exchange, emissions, neobase,
+    serde_json_helpers::{
+        serialize_f64_2_decimals_optional_none_as_minus_one,
+        serialize_u64_optional_none_as_minus_one,
+    },
 };
 
 use super::{
+    duplicates::find_duplicates,
     enriched_reco::{EnrichRecoError, EnrichedReco},
     typedefs::CountryCode,
     Search,
@@ -12,12 +19,26 @@ use super::{
 
 extern crate strum_macros;
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TripType {
     #[serde(rename = "OW")]
     OneWay,
     #[serde(rename = "RT")]
     RoundTrip,
+    /// The inbound does not start where the outbound landed (fly to LIS, back from OPO).
+    #[serde(rename = "OJD")]
+    DestinationOpenJaw,
+    /// The inbound does not land where the outbound started (fly from CDG, back to BRU).
+    #[serde(rename = "OJO")]
+    OriginOpenJaw,
+    #[serde(rename = "DOJ")]
+    DoubleOpenJaw,
+    /// Three journeys or more, back to the origin.
+    #[serde(rename = "CT")]
+    Circle,
+    /// Three journeys or more, not back to the origin.
+    #[serde(rename = "MC")]
+    MultiCity,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -98,6 +119,43 @@ pub struct EnrichedSearch {
     pub geo: Option<GeoType>,
     #[serde(rename = "OnD_distance")]
     pub ond_distance: u64,
+    // Price statistics over the recos, -1 when there are none
+    #[serde(
+        rename = "min_price_EUR",
+        serialize_with = "serialize_f64_2_decimals_optional_none_as_minus_one"
+    )]
+    pub min_price_eur: Option<f64>,
+    #[serde(
+        rename = "max_price_EUR",
+        serialize_with = "serialize_f64_2_decimals_optional_none_as_minus_one"
+    )]
+    pub max_price_eur: Option<f64>,
+    #[serde(
+        rename = "median_price_EUR",
+        serialize_with = "serialize_f64_2_decimals_optional_none_as_minus_one"
+    )]
+    pub median_price_eur: Option<f64>,
+    #[serde(serialize_with = "serialize_u64_optional_none_as_minus_one")]
+    pub cheapest_reco_index: Option<u64>,
+    #[serde(
+        rename = "cheapest_direct_price_EUR",
+        serialize_with = "serialize_f64_2_decimals_optional_none_as_minus_one"
+    )]
+    pub cheapest_direct_price_eur: Option<f64>,
+    pub nb_of_airlines: u64,
+    pub nb_of_duplicate_recos: u64,
+}
+
+/// Median of unsorted values, the mean of the two middle ones for an even count.
+fn median(values: &[f64]) -> Option<f64> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let middle = sorted.len() / 2;
+    match sorted.len() {
+        0 => None,
+        len if len % 2 == 1 => Some(sorted[middle]),
+        _ => Some((sorted[middle - 1] + sorted[middle]) / 2.0),
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -124,6 +182,8 @@ impl EnrichedSearch {
         search: &Search,
         neobase_locations: &neobase::Locations,
         exchange_rates: &currency_exchange::ExchangeRates,
+        emissions_model: &emissions::EmissionsModel,
+        airlines: &airlines::Airlines,
     ) -> Result<EnrichedSearch, EnrichSearchError> {
         let advance_purchase =
             u64::try_from((search.request_dep_date - search.search_date).num_days())
@@ -135,11 +195,6 @@ impl EnrichedSearch {
             .transpose()
             .map_err(EnrichSearchError::RequestDepDateAfterRequestReturnDate)?;
 
-        let trip_type = match &search.request_return_date {
-            Some(_) => TripType::RoundTrip,
-            None => TripType::OneWay,
-        };
-
         // decoding passengers string: "ADT=1,CH=2" means 1 Adult and 2 children
         let passengers = search
             .passengers_string
@@ -148,8 +203,11 @@ impl EnrichedSearch {
             .collect::<Result<Vec<Passenger>, ParsePassengerError>>()
             .map_err(EnrichSearchError::FailedToParsePassengersString)?;
 
-        let origin_country = neobase_locations.get_country_from_city(&search.origin_city);
-        let destination_country = neobase_locations.get_country_from_city(&search.destination_city);
+        let request_dep_date = Some(search.request_dep_date);
+        let origin_country =
+            neobase_locations.get_country_from_city(&search.origin_city, request_dep_date);
+        let destination_country =
+            neobase_locations.get_country_from_city(&search.destination_city, request_dep_date);
 
         let geo = if origin_country == destination_country {
             Some(GeoType::Domestic)
@@ -158,21 +216,89 @@ impl EnrichedSearch {
         };
 
         let ond_distance = neobase_locations
-            .get_round_distance_between_locations(&search.origin_city, &search.destination_city)
+            .get_round_distance_between_locations(
+                &search.origin_city,
+                &search.destination_city,
+                request_dep_date,
+            )
             .ok_or(EnrichSearchError::MissingLocationInDistanceCalculation {
-                origin_city: search.origin_city.clone(),
-                destination_city: search.destination_city.clone(),
+                origin_city: search.origin_city.to_string(),
+                destination_city: search.destination_city.to_string(),
             })?;
 
-        let recos = search
+        let mut recos = search
             .recos
             .iter()
             .map(|reco| {
-                EnrichedReco::enrich_from(reco, neobase_locations, exchange_rates, &search.currency)
+                EnrichedReco::enrich_from(
+                    reco,
+                    search,
+                    ond_distance,
+                    neobase_locations,
+                    exchange_rates,
+                    emissions_model,
+                    airlines,
+                )
             })
             .collect::<Result<Vec<EnrichedReco>, EnrichRecoError>>()
             .map_err(EnrichSearchError::EnrichReco)?;
 
+        // the trip type flown by all the recos, else the requested one, as recos may differ
+        let requested_trip_type = match &search.request_return_date {
+            Some(_) => TripType::RoundTrip,
+            None => TripType::OneWay,
+        };
+        let trip_type = match recos.split_first() {
+            Some((first, others))
+                if others.iter().all(|reco| reco.trip_type == first.trip_type) =>
+            {
+                first.trip_type
+            }
+            _ => requested_trip_type,
+        };
+
+        let prices: Vec<f64> = recos.iter().map(|reco| reco.price_eur).collect();
+        let min_price_eur = prices.iter().copied().min_by(f64::total_cmp);
+        let max_price_eur = prices.iter().copied().max_by(f64::total_cmp);
+        let median_price_eur = median(&prices);
+        // the first one wins on ties
+        let cheapest_reco_index = prices
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index as u64);
+        let cheapest_direct_price_eur = recos
+            .iter()
+            .filter(|reco| reco.is_direct)
+            .map(|reco| reco.price_eur)
+            .min_by(f64::total_cmp);
+        let nb_of_airlines = recos
+            .iter()
+            .flat_map(|reco| reco.flights.iter())
+            .map(|flight| flight.marketing_airline.as_str())
+            .collect::<HashSet<&str>>()
+            .len() as u64;
+
+        let duplicates = find_duplicates(
+            &recos
+                .iter()
+                .map(|reco| (reco.itinerary_fingerprint.as_str(), reco.price_eur))
+                .collect::<Vec<_>>(),
+        );
+        let nb_of_duplicate_recos =
+            duplicates.iter().filter(|duplicate| **duplicate).count() as u64;
+        for (reco, is_duplicate) in recos.iter_mut().zip(duplicates) {
+            reco.is_duplicate = is_duplicate;
+        }
+
+        // recos with the same price share the same rank
+        for reco in recos.iter_mut() {
+            reco.price_rank = 1 + prices
+                .iter()
+                .filter(|price| **price < reco.price_eur)
+                .count() as u64;
+        }
+
         Ok(EnrichedSearch {
             recos,
             advance_purchase,
@@ -183,6 +309,13 @@ impl EnrichedSearch {
             destination_country,
             geo,
             ond_distance,
+            min_price_eur,
+            max_price_eur,
+            median_price_eur,
+            cheapest_reco_index,
+            cheapest_direct_price_eur,
+            nb_of_airlines,
+            nb_of_duplicate_recos,
         })
     }
 }
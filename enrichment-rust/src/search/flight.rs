@@ -1,12 +1,44 @@
+use std::borrow::Cow;
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
 use serde::{Deserialize, Serialize};
 
-use super::typedefs::AirportCode;
+use crate::serde_json_helpers::{
+    borrow_cow_str_optional, hm_time_format_optional, ymd_date_format_optional,
+};
 
 #[derive(Serialize, Deserialize)]
-pub struct Flight {
-    pub dep_airport: AirportCode,
-    pub arr_airport: AirportCode,
-    pub marketing_airline: String,
-    pub operating_airline: Option<String>,
-    pub cabin: String,
+pub struct Flight<'a> {
+    #[serde(borrow)]
+    pub dep_airport: Cow<'a, str>,
+    #[serde(default, with = "ymd_date_format_optional")]
+    pub dep_date: Option<NaiveDate>,
+    #[serde(default, with = "hm_time_format_optional")]
+    pub dep_time: Option<NaiveTime>,
+    #[serde(borrow)]
+    pub arr_airport: Cow<'a, str>,
+    #[serde(default, with = "ymd_date_format_optional")]
+    pub arr_date: Option<NaiveDate>,
+    #[serde(default, with = "hm_time_format_optional")]
+    pub arr_time: Option<NaiveTime>,
+    #[serde(borrow)]
+    pub marketing_airline: Cow<'a, str>,
+    #[serde(default, borrow)]
+    pub flight_nb: Cow<'a, str>,
+    #[serde(default, borrow, deserialize_with = "borrow_cow_str_optional")]
+    pub operating_airline: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    pub cabin: Cow<'a, str>,
+}
+
+impl Flight<'_> {
+    /// Local departure date and time, when both are provided.
+    pub fn dep_datetime(&self) -> Option<NaiveDateTime> {
+        Some(self.dep_date?.and_time(self.dep_time?))
+    }
+
+    /// Local arrival date and time, when both are provided.
+    pub fn arr_datetime(&self) -> Option<NaiveDateTime> {
+        Some(self.arr_date?.and_time(self.arr_time?))
+    }
 }
@@ -1,20 +1,31 @@
+use std::borrow::Cow;
+
 use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 
 use crate::serde_json_helpers::ymd_date_format;
 use crate::{currency_exchange::Currency, serde_json_helpers::ymd_date_format_optional};
 
-use self::{reco::Reco, typedefs::CityCode};
+use self::reco::Reco;
 
+pub mod duplicates;
 pub mod enriched_flight;
 pub mod enriched_reco;
 pub mod enriched_search;
 pub mod flight;
 pub mod reco;
+pub mod routing;
 pub mod typedefs;
 
+/// A search as read from the input. Its strings are borrowed from the input text when they have
+/// no escapes.
 #[derive(Serialize, Deserialize)]
-pub struct Search {
+pub struct Search<'a> {
+    /// Empty when missing: only the database sinks need it.
+    #[serde(default, borrow)]
+    pub search_id: Cow<'a, str>,
+    #[serde(default, borrow)]
+    pub search_country: Cow<'a, str>,
     pub currency: Currency,
     #[serde(with = "ymd_date_format")]
     pub search_date: NaiveDate,
@@ -22,8 +33,12 @@ pub struct Search {
     pub request_dep_date: NaiveDate,
     #[serde(with = "ymd_date_format_optional")]
     pub request_return_date: Option<NaiveDate>,
-    pub passengers_string: String,
-    pub origin_city: CityCode,
-    pub destination_city: CityCode,
-    pub recos: Vec<Reco>,
+    #[serde(borrow)]
+    pub passengers_string: Cow<'a, str>,
+    #[serde(borrow)]
+    pub origin_city: Cow<'a, str>,
+    #[serde(borrow)]
+    pub destination_city: Cow<'a, str>,
+    #[serde(borrow)]
+    pub recos: Vec<Reco<'a>>,
 }
@@ -0,0 +1,123 @@
+use chrono::TimeDelta;
+
+use crate::neobase;
+
+use super::{enriched_flight::EnrichedFlight, enriched_search::TripType};
+
+/// Connections of 24 hours or more are stopovers, as in the IATA fare rules.
+const STOPOVER_MIN_HOURS: i64 = 24;
+
+/// Splits the flights of a reco into bounds: the outbound ends with the first flight landing in the
+/// destination city, everything after it is the inbound. One way recos have a single bound.
+pub fn split_into_bounds<'a>(
+    flights: &'a [EnrichedFlight],
+    destination_city: &str,
+) -> Vec<&'a [EnrichedFlight]> {
+    match flights
+        .iter()
+        .position(|flight| flight.arr_city == destination_city)
+    {
+        Some(last_outbound) if last_outbound + 1 < flights.len() => {
+            let (outbound, inbound) = flights.split_at(last_outbound + 1);
+            vec![outbound, inbound]
+        }
+        _ => vec![flights],
+    }
+}
+
+/// Whether the traveller stays at the place where `arriving` lands before taking `departing`:
+/// - when they leave from another city (a surface sector, as in open jaws),
+/// - when the connection lasts at least 24 hours,
+/// - when times are unknown, when they arrive in the destination city of the search.
+fn is_stopover(
+    arriving: &EnrichedFlight,
+    departing: &EnrichedFlight,
+    destination_city: &str,
+) -> bool {
+    if arriving.arr_city != departing.dep_city {
+        return true;
+    }
+    match (arriving.arr_datetime, departing.dep_datetime) {
+        (Some(arrival), Some(departure)) => {
+            departure - arrival >= TimeDelta::hours(STOPOVER_MIN_HOURS)
+        }
+        _ => arriving.arr_city == destination_city,
+    }
+}
+
+/// Splits the flights of a reco into journeys, each ending with a stopover (or the end of the trip).
+pub fn split_at_stopovers<'a>(
+    flights: &'a [EnrichedFlight],
+    destination_city: &str,
+) -> Vec<&'a [EnrichedFlight]> {
+    let mut journeys = vec![];
+    let mut start = 0;
+    for i in 1..flights.len() {
+        if is_stopover(&flights[i - 1], &flights[i], destination_city) {
+            journeys.push(&flights[start..i]);
+            start = i;
+        }
+    }
+    if start < flights.len() {
+        journeys.push(&flights[start..]);
+    }
+    journeys
+}
+
+/// Trip type actually flown by a reco, from the cities its journeys start and end in.
+pub fn detect_trip_type(flights: &[EnrichedFlight], destination_city: &str) -> TripType {
+    let journeys = split_at_stopovers(flights, destination_city);
+    // journeys are never empty
+    let origin = |journey: &[EnrichedFlight]| journey[0].dep_city.clone();
+    let destination = |journey: &[EnrichedFlight]| journey[journey.len() - 1].arr_city.clone();
+
+    match journeys.as_slice() {
+        [] | [_] => TripType::OneWay,
+        [outbound, inbound] => {
+            let same_turnaround = destination(outbound) == origin(inbound);
+            let back_home = destination(inbound) == origin(outbound);
+            match (same_turnaround, back_home) {
+                (true, true) => TripType::RoundTrip,
+                (false, true) => TripType::DestinationOpenJaw,
+                (true, false) => TripType::OriginOpenJaw,
+                (false, false) => TripType::DoubleOpenJaw,
+            }
+        }
+        [first, .., last] => {
+            if destination(last) == origin(first) {
+                TripType::Circle
+            } else {
+                TripType::MultiCity
+            }
+        }
+    }
+}
+
+/// A flight backtracks when it lands farther from where the bound is going than where it took off,
+/// like CDG→AMS on the way from PAR to LIS.
+pub fn flight_backtracks(
+    flight: &EnrichedFlight,
+    bound_destination: &str,
+    neobase_locations: &neobase::Locations,
+) -> bool {
+    let distance_to_destination = |airport: &str| {
+        neobase_locations.get_distance_between_locations(
+            airport,
+            bound_destination,
+            flight.dep_date,
+        )
+    };
+
+    match (
+        distance_to_destination(&flight.dep_airport),
+        distance_to_destination(&flight.arr_airport),
+    ) {
+        (Some(from_dep), Some(from_arr)) => from_arr.km() > from_dep.km(),
+        _ => false,
+    }
+}
+
+/// Ratio between the flown distance and the OnD distance. 1 for a perfectly direct routing.
+pub fn circuity(flown_distance: u64, ond_distance: u64) -> f64 {
+    flown_distance as f64 / ond_distance.max(1) as f64
+}
@@ -1,6 +1,10 @@
+use std::borrow::Cow;
+
 use serde::{de, Deserialize, Deserializer};
 use serde_json::Value;
 
+pub mod passthrough;
+
 pub fn deserialize_f64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
     Ok(match Value::deserialize(deserializer)? {
         Value::String(s) => s.parse().map_err(de::Error::custom)?,
@@ -9,6 +13,16 @@ pub fn deserialize_f64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64
     })
 }
 
+/// Like `#[serde(borrow)]` on a `Cow<str>`, which does not borrow when wrapped in an `Option`.
+pub fn borrow_cow_str_optional<'de: 'a, 'a, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Cow<'a, str>>, D::Error> {
+    #[derive(Deserialize)]
+    struct Borrowed<'a>(#[serde(borrow)] Cow<'a, str>);
+
+    Ok(Option::<Borrowed>::deserialize(deserializer)?.map(|borrowed| borrowed.0))
+}
+
 pub fn serialize_f64_2_decimals<S: serde::Serializer>(
     f: &f64,
     serializer: S,
@@ -16,6 +30,33 @@ pub fn serialize_f64_2_decimals<S: serde::Serializer>(
     serializer.serialize_f64((f * 100.0).round() / 100.0)
 }
 
+pub fn serialize_vec_f64_2_decimals<S: serde::Serializer>(
+    v: &[f64],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(v.iter().map(|f| (f * 100.0).round() / 100.0))
+}
+
+pub fn serialize_f64_2_decimals_optional<S: serde::Serializer>(
+    f: &Option<f64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match f {
+        Some(f) => serializer.serialize_f64((f * 100.0).round() / 100.0),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn serialize_f64_2_decimals_optional_none_as_minus_one<S: serde::Serializer>(
+    f: &Option<f64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match f {
+        Some(f) => serializer.serialize_f64((f * 100.0).round() / 100.0),
+        None => serializer.serialize_i64(-1),
+    }
+}
+
 pub fn serialize_u64_optional_none_as_minus_one<S: serde::Serializer>(
     f: &Option<u64>,
     serializer: S,
@@ -100,3 +141,36 @@ pub mod ymd_date_format_optional {
         }
     }
 }
+
+pub mod hm_time_format_optional {
+    use chrono::NaiveTime;
+    use serde::{self, Deserialize, Deserializer, Serializer};
+
+    const FORMAT: &str = "%H:%M";
+
+    pub fn serialize<S>(time: &Option<NaiveTime>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match time {
+            Some(time) => {
+                let s = format!("{}", time.format(FORMAT));
+                serializer.serialize_str(&s)
+            }
+            None => serializer.serialize_str(""),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<NaiveTime>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match String::deserialize(deserializer)?.as_str() {
+            "" => Ok(None),
+            s => {
+                let t = NaiveTime::parse_from_str(s, FORMAT).map_err(serde::de::Error::custom)?;
+                Ok(Some(t))
+            }
+        }
+    }
+}
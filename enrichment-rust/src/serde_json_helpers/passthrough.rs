@@ -0,0 +1,411 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{
+    self,
+    value::{SeqDeserializer, StrDeserializer},
+    DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
+use serde::ser::{self, Impossible, Serialize, SerializeMap, SerializeStruct, Serializer};
+use serde_json::value::RawValue;
+
+use crate::search::{
+    enriched_flight::EnrichedFlight, enriched_reco::EnrichedReco, enriched_search::EnrichedSearch,
+};
+
+/// An enriched struct, merged with the JSON object it was enriched from.
+pub trait Enriched: Serialize {
+    /// Field whose items are merged one by one with those of the input array of the same name.
+    const CHILDREN: Option<&'static str>;
+    type Child: Enriched;
+
+    fn children(&self) -> &[Self::Child];
+}
+
+impl Enriched for EnrichedSearch {
+    const CHILDREN: Option<&'static str> = Some("recos");
+    type Child = EnrichedReco;
+
+    fn children(&self) -> &[EnrichedReco] {
+        &self.recos
+    }
+}
+
+impl Enriched for EnrichedReco {
+    const CHILDREN: Option<&'static str> = Some("flights");
+    type Child = EnrichedFlight;
+
+    fn children(&self) -> &[EnrichedFlight] {
+        &self.flights
+    }
+}
+
+impl Enriched for EnrichedFlight {
+    const CHILDREN: Option<&'static str> = None;
+    type Child = EnrichedFlight;
+
+    fn children(&self) -> &[EnrichedFlight] {
+        &[]
+    }
+}
+
+/// A JSON object borrowed from the input text: the raw text of its fields, and the objects of
+/// its `Enriched::CHILDREN` array.
+///
+/// It deserializes into the struct the object was read for, like `Search`, from the text of each
+/// field, so that the input is not read twice.
+#[derive(Default)]
+pub struct RawObject<'a> {
+    fields: Vec<(Cow<'a, str>, &'a RawValue)>,
+    /// Key of the children, when the object has them.
+    children_key: Option<&'static str>,
+    pub children: Vec<RawObject<'a>>,
+}
+
+impl<'a> RawObject<'a> {
+    /// Reads the object an `E` is enriched from.
+    pub fn from_slice<E: Enriched>(input: &'a [u8]) -> Result<Self, serde_json::Error> {
+        let mut deserializer = serde_json::Deserializer::from_slice(input);
+        let raw_object = RawObjectSeed::<E>(PhantomData).deserialize(&mut deserializer)?;
+        deserializer.end()?;
+        Ok(raw_object)
+    }
+
+    /// The raw text of a field, other than the children.
+    pub fn field(&self, key: &str) -> Option<&'a RawValue> {
+        self.fields
+            .iter()
+            .find(|(field_key, _)| field_key == key)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// A key, borrowed unless it has escapes.
+struct Key<'a>(Cow<'a, str>);
+
+impl<'de> de::Deserialize<'de> for Key<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyVisitor;
+
+        impl<'de> Visitor<'de> for KeyVisitor {
+            type Value = Key<'de>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a key")
+            }
+
+            fn visit_borrowed_str<E: de::Error>(self, key: &'de str) -> Result<Key<'de>, E> {
+                Ok(Key(Cow::Borrowed(key)))
+            }
+
+            fn visit_str<E: de::Error>(self, key: &str) -> Result<Key<'de>, E> {
+                Ok(Key(Cow::Owned(key.to_string())))
+            }
+        }
+
+        deserializer.deserialize_str(KeyVisitor)
+    }
+}
+
+struct RawObjectSeed<E>(PhantomData<E>);
+
+impl<'de, E: Enriched> DeserializeSeed<'de> for RawObjectSeed<E> {
+    type Value = RawObject<'de>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, E: Enriched> Visitor<'de> for RawObjectSeed<E> {
+    type Value = RawObject<'de>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut raw_object = RawObject::default();
+        while let Some(Key(key)) = map.next_key()? {
+            if Some(key.as_ref()) == E::CHILDREN {
+                raw_object.children_key = E::CHILDREN;
+                raw_object.children = map.next_value_seed(RawArraySeed::<E::Child>(PhantomData))?;
+            } else {
+                raw_object.fields.push((key, map.next_value()?));
+            }
+        }
+        Ok(raw_object)
+    }
+}
+
+struct RawArraySeed<E>(PhantomData<E>);
+
+impl<'de, E: Enriched> DeserializeSeed<'de> for RawArraySeed<E> {
+    type Value = Vec<RawObject<'de>>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, E: Enriched> Visitor<'de> for RawArraySeed<E> {
+    type Value = Vec<RawObject<'de>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of objects")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut raw_objects = vec![];
+        while let Some(raw_object) = seq.next_element_seed(RawObjectSeed::<E>(PhantomData))? {
+            raw_objects.push(raw_object);
+        }
+        Ok(raw_objects)
+    }
+}
+
+impl<'de> Deserializer<'de> for &RawObject<'de> {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(RawObjectAccess {
+            raw_object: self,
+            index: 0,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, serde_json::Error> for &RawObject<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// The fields of a `RawObject`, then its children.
+struct RawObjectAccess<'r, 'de> {
+    raw_object: &'r RawObject<'de>,
+    index: usize,
+}
+
+impl<'de> MapAccess<'de> for RawObjectAccess<'_, 'de> {
+    type Error = serde_json::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, serde_json::Error> {
+        let fields = &self.raw_object.fields;
+        let key = match fields.get(self.index) {
+            Some((key, _)) => key.as_ref(),
+            None if self.index == fields.len() => match self.raw_object.children_key {
+                Some(key) => key,
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        seed.deserialize(StrDeserializer::new(key)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, serde_json::Error> {
+        self.index += 1;
+        match self.raw_object.fields.get(self.index - 1) {
+            Some((_, value)) => {
+                seed.deserialize(&mut serde_json::Deserializer::from_str(value.get()))
+            }
+            None => seed.deserialize(SeqDeserializer::new(self.raw_object.children.iter())),
+        }
+    }
+}
+
+/// Serializes like `merge_jsons` of the input object and the enriched struct: the enriched
+/// fields, then the input fields they do not override, written back verbatim.
+pub struct Merged<'r, 'a, E> {
+    pub raw_object: &'r RawObject<'a>,
+    pub enriched: &'r E,
+}
+
+impl<E: Enriched> Serialize for Merged<'_, '_, E> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        let mut keys = vec![];
+        self.enriched.serialize(FieldsSerializer {
+            map: &mut map,
+            skip: E::CHILDREN,
+            keys: &mut keys,
+        })?;
+        if let Some(children) = E::CHILDREN {
+            let no_raw_object = RawObject::default();
+            let merged_children: Vec<_> = self
+                .enriched
+                .children()
+                .iter()
+                .enumerate()
+                .map(|(index, enriched)| Merged {
+                    raw_object: self
+                        .raw_object
+                        .children
+                        .get(index)
+                        .unwrap_or(&no_raw_object),
+                    enriched,
+                })
+                .collect();
+            map.serialize_entry(children, &merged_children)?;
+        }
+        for (key, value) in &self.raw_object.fields {
+            if !keys.contains(&key.as_ref()) {
+                map.serialize_entry(key, value)?;
+            }
+        }
+        map.end()
+    }
+}
+
+/// Serializes the fields of a struct into a map being serialized, except `skip`, and records
+/// their keys.
+struct FieldsSerializer<'m, M> {
+    map: &'m mut M,
+    skip: Option<&'static str>,
+    keys: &'m mut Vec<&'static str>,
+}
+
+impl<M: SerializeMap> SerializeStruct for FieldsSerializer<'_, M> {
+    type Ok = ();
+    type Error = M::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), M::Error> {
+        self.keys.push(key);
+        if Some(key) == self.skip {
+            return Ok(());
+        }
+        self.map.serialize_entry(key, value)
+    }
+
+    fn end(self) -> Result<(), M::Error> {
+        Ok(())
+    }
+}
+
+macro_rules! not_a_struct {
+    ($($method:ident($($arg:ty),*)),* $(,)?) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<(), M::Error> {
+                Err(ser::Error::custom("expected a struct"))
+            }
+        )*
+    };
+}
+
+impl<'m, M: SerializeMap> Serializer for FieldsSerializer<'m, M> {
+    type Ok = ();
+    type Error = M::Error;
+    type SerializeSeq = Impossible<(), M::Error>;
+    type SerializeTuple = Impossible<(), M::Error>;
+    type SerializeTupleStruct = Impossible<(), M::Error>;
+    type SerializeTupleVariant = Impossible<(), M::Error>;
+    type SerializeMap = Impossible<(), M::Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), M::Error>;
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self, M::Error> {
+        Ok(self)
+    }
+
+    not_a_struct! {
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_none(),
+        serialize_unit(),
+        serialize_unit_struct(&'static str),
+        serialize_unit_variant(&'static str, u32, &'static str),
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _: &T) -> Result<(), M::Error> {
+        Err(ser::Error::custom("expected a struct"))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: &T,
+    ) -> Result<(), M::Error> {
+        Err(ser::Error::custom("expected a struct"))
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<(), M::Error> {
+        Err(ser::Error::custom("expected a struct"))
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, M::Error> {
+        Err(ser::Error::custom("expected a struct"))
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, M::Error> {
+        Err(ser::Error::custom("expected a struct"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, M::Error> {
+        Err(ser::Error::custom("expected a struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, M::Error> {
+        Err(ser::Error::custom("expected a struct"))
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, M::Error> {
+        Err(ser::Error::custom("expected a struct"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, M::Error> {
+        Err(ser::Error::custom("expected a struct"))
+    }
+}
@@ -0,0 +1,537 @@
+use std::io::Read;
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+use chrono::NaiveDate;
+use serde_json::json;
+use tiny_http::{Header, Method, Request};
+
+use crate::{
+    currency_exchange::Currency,
+    enrich_raw_search,
+    metrics::EnrichmentMetrics,
+    reference_data::{ReferenceData, ReferenceDataHandle},
+    search::enriched_search::EnrichedSearch,
+    serde_json_helpers::passthrough::RawObject,
+    EnrichJsonError, EnrichOptions,
+};
+
+const JSON: &str = "application/json";
+const NDJSON: &str = "application/x-ndjson";
+const TEXT: &str = "text/plain; charset=utf-8";
+/// Largest request body, in bytes, larger ones are answered with 413.
+pub const MAX_BODY_SIZE: u64 = 32 * 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ServerError {
+    #[error("Failed to listen on {address}: {reason}")]
+    Bind { address: String, reason: String },
+}
+
+/// A response, before it is sent.
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: serde_json::Value) -> Self {
+        Response {
+            status,
+            content_type: JSON,
+            body: body.to_string(),
+        }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Response::json(status, json!({ "error": message.into() }))
+    }
+
+    fn text(status: u16, body: &str) -> Self {
+        Response {
+            status,
+            content_type: TEXT,
+            body: body.to_string(),
+        }
+    }
+
+    fn metrics(metrics: &EnrichmentMetrics) -> Self {
+        Response {
+            status: 200,
+            content_type: crate::metrics::CONTENT_TYPE,
+            body: metrics.encode(),
+        }
+    }
+
+    fn send(self, request: Request) {
+        let header = Header::from_bytes("Content-Type", self.content_type).unwrap();
+        // the client may be gone
+        let _ = request.respond(
+            tiny_http::Response::from_string(self.body)
+                .with_status_code(self.status)
+                .with_header(header),
+        );
+    }
+}
+
+/// Serves the enrichment over HTTP:
+/// - `POST /enrich`: a search JSON, or one per line with the `application/x-ndjson` content
+///   type, enriched like `enrich_json_str`
+/// - `GET /locations/{code}?date=YYYY-MM-DD`: the NeoBase record of an IATA code
+/// - `GET /rates/{currency}`: the exchange rate of a currency, per euro
+/// - `GET /health`: whether the server runs, and `GET /ready`: whether the reference data is
+///   loaded, as it loads after the server starts listening. Each request uses a snapshot of the
+///   reference data, that reloads do not change.
+/// - `GET /metrics`: the `EnrichmentMetrics` of the enriched searches
+pub struct EnrichmentServer {
+    server: tiny_http::Server,
+    reference_data: OnceLock<Arc<ReferenceDataHandle>>,
+    metrics: EnrichmentMetrics,
+}
+
+impl EnrichmentServer {
+    /// Listens on an address like `0.0.0.0:8080`.
+    pub fn bind(address: &str) -> Result<Self, ServerError> {
+        let server = tiny_http::Server::http(address).map_err(|error| ServerError::Bind {
+            address: address.to_string(),
+            reason: error.to_string(),
+        })?;
+        Ok(EnrichmentServer {
+            server,
+            reference_data: OnceLock::new(),
+            metrics: EnrichmentMetrics::new(),
+        })
+    }
+
+    /// Makes the server ready. Reference data is set once: later calls are ignored, reloads go
+    /// through the handle.
+    pub fn set_reference_data(&self, reference_data: Arc<ReferenceDataHandle>) {
+        let _ = self.reference_data.set(reference_data);
+    }
+
+    /// Handles requests on `threads` threads, forever.
+    pub fn run(&self, threads: usize) {
+        std::thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                scope.spawn(|| {
+                    for request in self.server.incoming_requests() {
+                        self.respond(request);
+                    }
+                });
+            }
+        });
+    }
+
+    fn respond(&self, mut request: Request) {
+        let content_type = request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Content-Type"))
+            .map(|header| header.value.as_str().to_string());
+        let reference_data = self.reference_data.get().map(|handle| handle.snapshot());
+        let body = match request.body_length() {
+            Some(length) if length as u64 > MAX_BODY_SIZE => Err(body_too_large()),
+            _ => read_body(request.as_reader(), MAX_BODY_SIZE),
+        };
+        let response = match body {
+            Ok(body) => catch_panics(|| {
+                handle(
+                    reference_data.as_deref(),
+                    &self.metrics,
+                    request.method(),
+                    request.url(),
+                    content_type.as_deref(),
+                    &body,
+                )
+            }),
+            Err(response) => response,
+        };
+        response.send(request);
+    }
+}
+
+/// Serves `GET /metrics` only, in a background thread, for the modes without a server.
+pub fn serve_metrics(address: &str, metrics: Arc<EnrichmentMetrics>) -> Result<(), ServerError> {
+    let server = tiny_http::Server::http(address).map_err(|error| ServerError::Bind {
+        address: address.to_string(),
+        reason: error.to_string(),
+    })?;
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = match (request.method(), request.url()) {
+                (Method::Get, "/metrics") => Response::metrics(&metrics),
+                (_, path) => Response::error(404, format!("Unknown path {path}")),
+            };
+            response.send(request);
+        }
+    });
+    Ok(())
+}
+
+fn body_too_large() -> Response {
+    Response::error(413, format!("Bodies are {MAX_BODY_SIZE} bytes at most"))
+}
+
+/// Reads a body of `max_size` bytes at most, including those without a length.
+fn read_body(reader: impl Read, max_size: u64) -> Result<Vec<u8>, Response> {
+    let mut body = vec![];
+    match reader.take(max_size + 1).read_to_end(&mut body) {
+        Ok(_) if body.len() as u64 > max_size => Err(body_too_large()),
+        Ok(_) => Ok(body),
+        Err(error) => Err(Response::error(
+            400,
+            format!("Failed to read body: {error}"),
+        )),
+    }
+}
+
+/// Answers 500 when a request panics, instead of ending the thread handling requests.
+fn catch_panics(respond: impl FnOnce() -> Response) -> Response {
+    panic::catch_unwind(AssertUnwindSafe(respond))
+        .unwrap_or_else(|_| Response::error(500, "Internal error"))
+}
+
+fn handle(
+    reference_data: Option<&ReferenceData>,
+    metrics: &EnrichmentMetrics,
+    method: &Method,
+    url: &str,
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Response {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        (Method::Get, ["health"]) => Response::text(200, "ok"),
+        (Method::Get, ["ready"]) => match reference_data {
+            Some(_) => Response::text(200, "ready"),
+            None => Response::text(503, "loading reference data"),
+        },
+        (Method::Get, ["metrics"]) => Response::metrics(metrics),
+        (_, ["health" | "ready" | "metrics"]) => Response::error(405, "Use GET"),
+        (Method::Post, ["enrich"]) => loaded(reference_data, |reference_data| {
+            if content_type.is_some_and(|content_type| content_type.starts_with(NDJSON)) {
+                enrich_ndjson(reference_data, metrics, body)
+            } else {
+                enrich(reference_data, metrics, body)
+            }
+        }),
+        (Method::Get, ["locations", code]) => loaded(reference_data, |reference_data| {
+            location(reference_data, code, query)
+        }),
+        (Method::Get, ["rates", currency]) => loaded(reference_data, |reference_data| {
+            rate(reference_data, currency)
+        }),
+        (_, ["enrich"]) => Response::error(405, "Use POST"),
+        (_, ["locations" | "rates", _]) => Response::error(405, "Use GET"),
+        _ => Response::error(404, format!("Unknown path {path}")),
+    }
+}
+
+/// Responds with the reference data, once it is loaded.
+fn loaded(
+    reference_data: Option<&ReferenceData>,
+    respond: impl FnOnce(&ReferenceData) -> Response,
+) -> Response {
+    match reference_data {
+        Some(reference_data) => respond(reference_data),
+        None => Response::error(503, "Reference data is loading"),
+    }
+}
+
+fn enrich(reference_data: &ReferenceData, metrics: &EnrichmentMetrics, body: &[u8]) -> Response {
+    match enrich_text(reference_data, metrics, body) {
+        Ok(output) => Response {
+            status: 200,
+            content_type: JSON,
+            body: output,
+        },
+        Err(EnrichTextError::InvalidJson(error)) => {
+            Response::error(400, format!("Invalid JSON: {error}"))
+        }
+        Err(EnrichTextError::Enrich(error)) => Response::error(422, error.to_string()),
+    }
+}
+
+/// Enriches each line, and answers one line per search: the enriched search, or an error with
+/// its line number.
+fn enrich_ndjson(
+    reference_data: &ReferenceData,
+    metrics: &EnrichmentMetrics,
+    body: &[u8],
+) -> Response {
+    let mut lines = String::new();
+    let input_lines = body
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.trim_ascii().is_empty());
+    for (index, line) in input_lines.enumerate() {
+        match enrich_text(reference_data, metrics, line) {
+            Ok(output) => lines.push_str(&output),
+            Err(error) => {
+                let error = match error {
+                    EnrichTextError::InvalidJson(error) => format!("Invalid JSON: {error}"),
+                    EnrichTextError::Enrich(error) => error.to_string(),
+                };
+                lines.push_str(&json!({ "error": error, "search_nb": index + 1 }).to_string());
+            }
+        }
+        lines.push('\n');
+    }
+    Response {
+        status: 200,
+        content_type: NDJSON,
+        body: lines,
+    }
+}
+
+enum EnrichTextError {
+    InvalidJson(serde_json::Error),
+    Enrich(EnrichJsonError),
+}
+
+/// Enriches a search like `enrich_json_str`, without building a `serde_json::Value`.
+fn enrich_text(
+    reference_data: &ReferenceData,
+    metrics: &EnrichmentMetrics,
+    input: &[u8],
+) -> Result<String, EnrichTextError> {
+    let raw_search = RawObject::from_slice::<EnrichedSearch>(input).map_err(|error| {
+        metrics.record_read_error();
+        EnrichTextError::InvalidJson(error)
+    })?;
+    // no recos are dropped without `keep_only_cheapest_per_itinerary`
+    let nb_of_recos = raw_search.children.len();
+    let output = metrics
+        .observe_raw(
+            raw_search,
+            |raw_search| {
+                enrich_raw_search(
+                    raw_search,
+                    &reference_data.locations,
+                    &reference_data.exchange_rates,
+                    &reference_data.emissions_model,
+                    &reference_data.airlines,
+                    EnrichOptions::default(),
+                )
+            },
+            |_| nb_of_recos,
+        )
+        .map_err(EnrichTextError::Enrich)?;
+    Ok(String::from_utf8(output).expect("serde_json writes UTF-8"))
+}
+
+fn location(reference_data: &ReferenceData, code: &str, query: &str) -> Response {
+    let date = query
+        .split('&')
+        .find_map(|parameter| parameter.strip_prefix("date="));
+    let date = match date.map(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d")) {
+        None => None,
+        Some(Ok(date)) => Some(date),
+        Some(Err(_)) => return Response::error(400, "Dates are YYYY-MM-DD"),
+    };
+    match reference_data
+        .locations
+        .get_location(&code.to_uppercase(), date)
+    {
+        Some(location) => Response::json(200, serde_json::to_value(location).unwrap()),
+        None => Response::error(404, format!("Unknown location {code}")),
+    }
+}
+
+fn rate(reference_data: &ReferenceData, currency: &str) -> Response {
+    let rate = Currency::from_str(&currency.to_uppercase())
+        .ok()
+        .and_then(|currency| Some((currency, reference_data.exchange_rates.get_rate(&currency)?)));
+    match rate {
+        Some((currency, rate)) => Response::json(
+            200,
+            json!({
+                "currency": currency,
+                "rate": rate,
+                "date": reference_data.exchange_rates.record_date(),
+            }),
+        ),
+        None => Response::error(404, format!("Unknown currency {currency}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{reference_data, sample_search};
+
+    fn search() -> String {
+        sample_search().to_string()
+    }
+
+    fn body(response: &Response) -> serde_json::Value {
+        serde_json::from_str(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_health_and_readiness() {
+        let reference_data = reference_data();
+        let metrics = EnrichmentMetrics::new();
+        assert_eq!(
+            handle(None, &metrics, &Method::Get, "/health", None, b"").status,
+            200
+        );
+        assert_eq!(
+            handle(None, &metrics, &Method::Get, "/ready", None, b"").status,
+            503
+        );
+        assert_eq!(
+            handle(
+                None,
+                &metrics,
+                &Method::Post,
+                "/enrich",
+                None,
+                search().as_bytes()
+            )
+            .status,
+            503
+        );
+        let ready = handle(
+            Some(&reference_data),
+            &metrics,
+            &Method::Get,
+            "/ready",
+            None,
+            b"",
+        );
+        assert_eq!(ready.status, 200);
+        let unknown = handle(
+            Some(&reference_data),
+            &metrics,
+            &Method::Get,
+            "/unknown",
+            None,
+            b"",
+        );
+        assert_eq!(unknown.status, 404);
+    }
+
+    #[test]
+    fn test_enrich() {
+        let reference_data = reference_data();
+        let metrics = EnrichmentMetrics::new();
+        let response = handle(
+            Some(&reference_data),
+            &metrics,
+            &Method::Post,
+            "/enrich",
+            Some(JSON),
+            search().as_bytes(),
+        );
+        assert_eq!(response.status, 200, "{}", response.body);
+        assert_eq!(body(&response)["trip_type"], "RT");
+        assert_eq!(body(&response)["recos"][0]["price_EUR"], 578.72);
+
+        let invalid = handle(
+            Some(&reference_data),
+            &metrics,
+            &Method::Post,
+            "/enrich",
+            None,
+            b"{",
+        );
+        assert_eq!(invalid.status, 400);
+        let get = handle(
+            Some(&reference_data),
+            &metrics,
+            &Method::Get,
+            "/enrich",
+            None,
+            b"",
+        );
+        assert_eq!(get.status, 405);
+
+        let batch = format!("{search}\n\n{{}}\n{search}\n", search = search());
+        let response = handle(
+            Some(&reference_data),
+            &metrics,
+            &Method::Post,
+            "/enrich",
+            Some(NDJSON),
+            batch.as_bytes(),
+        );
+        assert_eq!(response.content_type, NDJSON);
+        let lines: Vec<serde_json::Value> = response
+            .body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["search_id"], "LRX-51980-1637149713-8763");
+        assert_eq!(lines[1]["search_nb"], 2);
+        assert!(lines[1]["error"].is_string());
+        assert_eq!(lines[2]["search_id"], "LRX-51980-1637149713-8763");
+
+        let response = handle(None, &metrics, &Method::Get, "/metrics", None, b"");
+        assert_eq!(response.status, 200);
+        assert!(response
+            .body
+            .contains("enrichment_searches_enriched_total 3"));
+        assert!(response
+            .body
+            .contains("enrichment_searches_failed_total{error=\"invalid_input\"} 1"));
+    }
+
+    #[test]
+    fn test_body_limits() {
+        assert_eq!(read_body(&b"{}"[..], 2), Ok(b"{}".to_vec()));
+        assert_eq!(read_body(&b"{ }"[..], 2).unwrap_err().status, 413);
+        assert_eq!(catch_panics(|| panic!("enrichment bug")).status, 500);
+        assert_eq!(catch_panics(|| Response::text(200, "ok")).status, 200);
+    }
+
+    #[test]
+    fn test_reference_data() {
+        let reference_data = reference_data();
+        let metrics = EnrichmentMetrics::new();
+        let response = handle(
+            Some(&reference_data),
+            &metrics,
+            &Method::Get,
+            "/locations/ory?date=2021-11-17",
+            None,
+            b"",
+        );
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response)["city_code_list"][0], "PAR");
+        let unknown = handle(
+            Some(&reference_data),
+            &metrics,
+            &Method::Get,
+            "/locations/XXX",
+            None,
+            b"",
+        );
+        assert_eq!(unknown.status, 404);
+
+        let response = handle(
+            Some(&reference_data),
+            &metrics,
+            &Method::Get,
+            "/rates/USD",
+            None,
+            b"",
+        );
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response)["rate"], 1.1271);
+        assert_eq!(body(&response)["date"], "19 November 2021");
+        let unknown = handle(
+            Some(&reference_data),
+            &metrics,
+            &Method::Get,
+            "/rates/XXX",
+            None,
+            b"",
+        );
+        assert_eq!(unknown.status, 404);
+    }
+}
@@ -0,0 +1,4 @@
+//! Databases the flattened searches can be written to.
+
+pub mod postgres;
+pub mod sqlite;
@@ -0,0 +1,79 @@
+-- Flattened enriched searches: one row per search, reco and flight.
+-- Recos and flights are deleted with their search, which is how searches are replaced on replays.
+
+CREATE TABLE db_searches (
+    "search_id" text PRIMARY KEY,
+    "search_country" text NOT NULL,
+    "search_date" date NOT NULL,
+    "request_dep_date" date NOT NULL,
+    "request_return_date" date,
+    "advance_purchase" bigint NOT NULL,
+    "stay_duration" bigint,
+    "trip_type" text NOT NULL,
+    "OnD" text NOT NULL,
+    "origin_city" text NOT NULL,
+    "destination_city" text NOT NULL,
+    "origin_country" text NOT NULL,
+    "destination_country" text NOT NULL,
+    "geo" text,
+    "OnD_distance" bigint NOT NULL,
+    "currency" text NOT NULL,
+    "nb_of_recos" bigint NOT NULL,
+    "min_price_EUR" numeric(18, 2),
+    "max_price_EUR" numeric(18, 2),
+    "median_price_EUR" numeric(18, 2),
+    "cheapest_direct_price_EUR" numeric(18, 2),
+    "nb_of_airlines" bigint NOT NULL
+);
+
+CREATE INDEX db_searches_search_date ON db_searches ("search_date");
+CREATE INDEX db_searches_ond ON db_searches ("OnD");
+
+CREATE TABLE db_recos (
+    "search_id" text NOT NULL REFERENCES db_searches ON DELETE CASCADE,
+    "reco_index" bigint NOT NULL,
+    "nb_of_flights" bigint NOT NULL,
+    "price_EUR" numeric(18, 2) NOT NULL,
+    "taxes_EUR" numeric(18, 2) NOT NULL,
+    "fees_EUR" numeric(18, 2) NOT NULL,
+    "main_marketing_airline" text NOT NULL,
+    "main_operating_airline" text NOT NULL,
+    "main_airline_alliance" text,
+    "main_cabin" text NOT NULL,
+    "trip_type" text NOT NULL,
+    "flown_distance" bigint NOT NULL,
+    "co2_kg" double precision NOT NULL,
+    "circuity" double precision NOT NULL,
+    "is_direct" boolean NOT NULL,
+    "is_lcc" boolean NOT NULL,
+    "is_codeshare" boolean NOT NULL,
+    "is_interline" boolean NOT NULL,
+    "is_duplicate" boolean NOT NULL,
+    "price_rank" bigint NOT NULL,
+    "itinerary_fingerprint" text NOT NULL,
+    PRIMARY KEY ("search_id", "reco_index")
+);
+
+CREATE INDEX db_recos_main_marketing_airline ON db_recos ("main_marketing_airline");
+
+CREATE TABLE db_flights (
+    "search_id" text NOT NULL,
+    "reco_index" bigint NOT NULL,
+    "flight_index" bigint NOT NULL,
+    "dep_airport" text NOT NULL,
+    "arr_airport" text NOT NULL,
+    "dep_city" text NOT NULL,
+    "arr_city" text NOT NULL,
+    "dep_datetime" timestamp,
+    "arr_datetime" timestamp,
+    "marketing_airline" text NOT NULL,
+    "operating_airline" text NOT NULL,
+    "flight_nb" text NOT NULL,
+    "cabin" text NOT NULL,
+    "distance" bigint NOT NULL,
+    "co2_kg" double precision NOT NULL,
+    "is_rail" boolean NOT NULL,
+    "is_bus" boolean NOT NULL,
+    PRIMARY KEY ("search_id", "reco_index", "flight_index"),
+    FOREIGN KEY ("search_id", "reco_index") REFERENCES db_recos ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+-- Normalized cabin of the recos (M, W, C, F), null when the cabin code is unknown.
+
+ALTER TABLE db_recos ADD COLUMN "main_cabin_class" text;
@@ -0,0 +1,215 @@
+use std::{collections::HashMap, io::Write};
+
+use postgres::{Client, NoTls, Transaction};
+
+use crate::flatten::{Column, ColumnType, FlatSearch, Row, Value};
+
+/// Schema migrations, applied in order. Never edit an applied one: add a new one instead.
+const MIGRATIONS: &[(i32, &str, &str)] = &[
+    (
+        1,
+        "create tables",
+        include_str!("migrations/0001_create_tables.sql"),
+    ),
+    (
+        2,
+        "add main cabin class",
+        include_str!("migrations/0002_add_main_cabin_class.sql"),
+    ),
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum PostgresSinkError {
+    #[error("Postgres error: {0:?}")]
+    Postgres(#[from] postgres::Error),
+    #[error("Failed to copy rows: {0:?}")]
+    Copy(#[from] std::io::Error),
+    #[error("A search has no search_id, which searches are replaced by")]
+    MissingSearchId,
+}
+
+/// Writes flattened searches to the `db_searches`, `db_recos` and `db_flights` tables.
+/// Writing a search again replaces it, with its recos and flights, so replays are idempotent.
+pub struct PostgresSink {
+    client: Client,
+}
+
+impl PostgresSink {
+    /// Connects with a libpq-style string, like
+    /// `host=localhost user=postgres password=password` for the dev docker-compose,
+    /// and applies the pending migrations.
+    pub fn connect(params: &str) -> Result<Self, PostgresSinkError> {
+        let mut sink = PostgresSink {
+            client: Client::connect(params, NoTls)?,
+        };
+        sink.migrate()?;
+        Ok(sink)
+    }
+
+    /// Applies the migrations not yet recorded in `schema_migrations`, each in its own transaction.
+    pub fn migrate(&mut self) -> Result<(), PostgresSinkError> {
+        self.client.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version integer PRIMARY KEY,
+                name text NOT NULL,
+                applied_at timestamptz NOT NULL DEFAULT now()
+            )",
+        )?;
+
+        for (version, name, sql) in MIGRATIONS {
+            let mut transaction = self.client.transaction()?;
+            // concurrent sinks wait for the first one to migrate
+            transaction.batch_execute("LOCK TABLE schema_migrations IN EXCLUSIVE MODE")?;
+            let applied = transaction
+                .query_opt(
+                    "SELECT 1 FROM schema_migrations WHERE version = $1",
+                    &[version],
+                )?
+                .is_some();
+            if !applied {
+                transaction.batch_execute(sql)?;
+                transaction.execute(
+                    "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+                    &[version, name],
+                )?;
+            }
+            transaction.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Writes searches in a single transaction, replacing those already stored.
+    /// When a search appears several times, the last one in the order of `searches` wins, so
+    /// replacement follows the input order only if the searches are written in that order.
+    /// Searches without a `search_id` would replace each other: nothing is written if there is one.
+    pub fn write(&mut self, searches: &[FlatSearch]) -> Result<(), PostgresSinkError> {
+        if searches
+            .iter()
+            .any(|search| search.search.search_id.is_empty())
+        {
+            return Err(PostgresSinkError::MissingSearchId);
+        }
+        let mut last_index: HashMap<&str, usize> = HashMap::new();
+        for (index, search) in searches.iter().enumerate() {
+            last_index.insert(&search.search.search_id, index);
+        }
+        let searches: Vec<&FlatSearch> = searches
+            .iter()
+            .enumerate()
+            .filter(|(index, search)| last_index[search.search.search_id.as_str()] == *index)
+            .map(|(_, search)| search)
+            .collect();
+        let search_ids: Vec<&str> = searches
+            .iter()
+            .map(|search| search.search.search_id.as_str())
+            .collect();
+
+        let mut transaction = self.client.transaction()?;
+        // recos and flights are deleted in cascade
+        transaction.execute(
+            "DELETE FROM db_searches WHERE search_id = ANY($1)",
+            &[&search_ids],
+        )?;
+        copy_in(
+            &mut transaction,
+            searches.iter().map(|search| &search.search),
+        )?;
+        copy_in(
+            &mut transaction,
+            searches.iter().flat_map(|search| search.recos.iter()),
+        )?;
+        copy_in(
+            &mut transaction,
+            searches.iter().flat_map(|search| search.flights.iter()),
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Escapes a value for the text format of COPY, where `\N` is NULL.
+fn copy_text(column: &Column, value: &Value) -> String {
+    match value {
+        Value::Null => "\\N".to_string(),
+        Value::String(s) => s
+            .replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r"),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) if column.column_type == ColumnType::Money => format!("{f:.2}"),
+        Value::Float(f) => f.to_string(),
+        Value::Boolean(b) => if *b { "t" } else { "f" }.to_string(),
+        Value::Date(date) => date.format("%Y-%m-%d").to_string(),
+        Value::DateTime(datetime) => datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+    }
+}
+
+/// Bulk-inserts rows with COPY, much faster than one INSERT per row.
+fn copy_in<'a, R: Row + 'a>(
+    transaction: &mut Transaction,
+    rows: impl Iterator<Item = &'a R>,
+) -> Result<(), PostgresSinkError> {
+    let columns = R::schema()
+        .iter()
+        .map(|column| format!("\"{}\"", column.name))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let mut writer = transaction.copy_in(&format!("COPY {} ({columns}) FROM STDIN", R::TABLE))?;
+    for row in rows {
+        let line = R::schema()
+            .iter()
+            .zip(row.values())
+            .map(|(column, value)| copy_text(column, &value))
+            .collect::<Vec<String>>()
+            .join("\t");
+        writeln!(writer, "{line}")?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{enrich_to_rows, sample_search};
+
+    #[test]
+    #[ignore = "needs a Postgres, like the one of docs/dev/docker-compose.yml"]
+    fn test_postgres_sink() {
+        let params = std::env::var("POSTGRES_URL")
+            .unwrap_or("host=localhost user=postgres password=password".to_string());
+        let rows = || enrich_to_rows(sample_search());
+
+        let mut sink = PostgresSink::connect(&params).unwrap();
+        sink.migrate().unwrap();
+        // replays replace the search
+        sink.write(&[rows(), rows()]).unwrap();
+        sink.write(&[rows()]).unwrap();
+
+        let mut client = Client::connect(&params, NoTls).unwrap();
+        let count = |client: &mut Client, table: &str| -> i64 {
+            client
+                .query_one(
+                    &format!(
+                        "SELECT count(*) FROM {table} WHERE search_id = 'LRX-51980-1637149713-8763'"
+                    ),
+                    &[],
+                )
+                .unwrap()
+                .get(0)
+        };
+        assert_eq!(count(&mut client, "db_searches"), 1);
+        assert_eq!(count(&mut client, "db_recos"), 3);
+        assert_eq!(count(&mut client, "db_flights"), 7);
+        let row = client
+            .query_one(
+                "SELECT \"OnD\", \"price_EUR\"::text FROM db_searches JOIN db_recos USING (search_id) \
+                 WHERE search_id = 'LRX-51980-1637149713-8763' AND reco_index = 0",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(row.get::<_, String>(0), "PAR-LIS");
+        assert_eq!(row.get::<_, String>(1), "578.72");
+    }
+}
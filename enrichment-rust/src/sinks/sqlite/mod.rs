@@ -0,0 +1,218 @@
+use std::path::Path;
+
+use rusqlite::{params_from_iter, types, Connection, Transaction};
+
+use crate::flatten::{ColumnType, FlatSearch, FlightRow, RecoRow, Row, SearchRow, Value};
+
+/// Indexes for the price benchmark queries of `backend/server.py`. Joins on `search_id` use the
+/// primary keys.
+const INDEXES: &[(&str, &str)] = &[
+    ("db_searches", "OnD"),
+    ("db_searches", "search_date"),
+    ("db_recos", "main_marketing_airline"),
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum SqliteSinkError {
+    #[error("SQLite error: {0:?}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("A search has no search_id, which searches are replaced by")]
+    MissingSearchId,
+}
+
+/// Writes flattened searches to `db_searches`, `db_recos` and `db_flights` tables in an embedded
+/// SQLite database. Writing a search again replaces it, with its recos and flights.
+pub struct SqliteSink {
+    connection: Connection,
+}
+
+fn sql_type(column_type: ColumnType) -> &'static str {
+    match column_type {
+        ColumnType::String | ColumnType::Code => "TEXT",
+        ColumnType::Integer | ColumnType::Boolean => "INTEGER",
+        // SQLite has no decimal type
+        ColumnType::Float | ColumnType::Money => "REAL",
+        // ISO 8601 text compares like dates
+        ColumnType::Date | ColumnType::DateTime => "TEXT",
+    }
+}
+
+fn create_table<R: Row>(primary_key: &[&str]) -> String {
+    let columns = R::schema()
+        .iter()
+        .map(|column| {
+            let not_null = if column.nullable { "" } else { " NOT NULL" };
+            format!(
+                "\"{}\" {}{not_null}",
+                column.name,
+                sql_type(column.column_type)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    let primary_key = primary_key
+        .iter()
+        .map(|column| format!("\"{column}\""))
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} ({columns}, PRIMARY KEY ({primary_key}))",
+        R::TABLE
+    )
+}
+
+fn sql_value(value: Value) -> types::Value {
+    match value {
+        Value::Null => types::Value::Null,
+        Value::String(s) => types::Value::Text(s),
+        Value::Integer(i) => types::Value::Integer(i),
+        Value::Float(f) => types::Value::Real(f),
+        Value::Boolean(b) => types::Value::Integer(b.into()),
+        Value::Date(date) => types::Value::Text(date.format("%Y-%m-%d").to_string()),
+        Value::DateTime(datetime) => {
+            types::Value::Text(datetime.format("%Y-%m-%d %H:%M:%S").to_string())
+        }
+    }
+}
+
+fn insert<R: Row>(transaction: &Transaction, row: &R) -> Result<(), SqliteSinkError> {
+    let schema = R::schema();
+    let columns = schema
+        .iter()
+        .map(|column| format!("\"{}\"", column.name))
+        .collect::<Vec<String>>()
+        .join(", ");
+    let placeholders = (1..=schema.len())
+        .map(|i| format!("?{i}"))
+        .collect::<Vec<String>>()
+        .join(", ");
+    transaction
+        .prepare_cached(&format!(
+            "INSERT INTO {} ({columns}) VALUES ({placeholders})",
+            R::TABLE
+        ))?
+        .execute(params_from_iter(row.values().into_iter().map(sql_value)))?;
+    Ok(())
+}
+
+impl SqliteSink {
+    /// Opens (or creates) a database file, and creates the missing tables and indexes.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SqliteSinkError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, SqliteSinkError> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<Self, SqliteSinkError> {
+        connection.execute(&create_table::<SearchRow>(&["search_id"]), [])?;
+        connection.execute(&create_table::<RecoRow>(&["search_id", "reco_index"]), [])?;
+        connection.execute(
+            &create_table::<FlightRow>(&["search_id", "reco_index", "flight_index"]),
+            [],
+        )?;
+        for (table, column) in INDEXES {
+            connection.execute(
+                &format!("CREATE INDEX IF NOT EXISTS {table}_{column} ON {table} (\"{column}\")"),
+                [],
+            )?;
+        }
+        Ok(SqliteSink { connection })
+    }
+
+    /// The underlying connection, to query the tables.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Writes searches in a single transaction, replacing those already stored. When a search
+    /// appears several times, the last one in the order of `searches` wins. Searches without a
+    /// `search_id` would replace each other: nothing is written if there is one.
+    pub fn write(&mut self, searches: &[FlatSearch]) -> Result<(), SqliteSinkError> {
+        if searches
+            .iter()
+            .any(|search| search.search.search_id.is_empty())
+        {
+            return Err(SqliteSinkError::MissingSearchId);
+        }
+        let transaction = self.connection.transaction()?;
+        for search in searches {
+            for table in [SearchRow::TABLE, RecoRow::TABLE, FlightRow::TABLE] {
+                transaction
+                    .prepare_cached(&format!("DELETE FROM {table} WHERE search_id = ?1"))?
+                    .execute([&search.search.search_id])?;
+            }
+            insert(&transaction, &search.search)?;
+            for reco in &search.recos {
+                insert(&transaction, reco)?;
+            }
+            for flight in &search.flights {
+                insert(&transaction, flight)?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{enrich_to_rows, sample_search};
+
+    #[test]
+    fn test_sqlite_sink() {
+        let rows = || enrich_to_rows(sample_search());
+
+        let mut sink = SqliteSink::open_in_memory().unwrap();
+        sink.write(&[rows(), rows()]).unwrap();
+        sink.write(&[rows()]).unwrap();
+
+        // cheapest price per search, airline and advance purchase, as in backend/server.py
+        let mut statement = sink
+            .connection()
+            .prepare(
+                "SELECT MIN(db_recos.price_EUR), db_recos.main_marketing_airline, s.advance_purchase
+                FROM db_recos
+                JOIN (
+                    SELECT search_id, advance_purchase
+                    FROM db_searches
+                    WHERE OnD LIKE 'PAR-LIS' AND trip_type LIKE 'RT'
+                        AND search_date >= '2021-11-01' AND search_date <= '2021-11-30'
+                ) AS s ON s.search_id = db_recos.search_id
+                GROUP BY db_recos.search_id, db_recos.main_marketing_airline, s.advance_purchase
+                ORDER BY db_recos.main_marketing_airline",
+            )
+            .unwrap();
+        let prices: Vec<(f64, String, i64)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            prices,
+            vec![
+                (578.72, "KL".to_string(), 30),
+                (199.99, "TP".to_string(), 30)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_search_id() {
+        let mut search = sample_search();
+        search.as_object_mut().unwrap().remove("search_id");
+
+        let mut sink = SqliteSink::open_in_memory().unwrap();
+        assert!(matches!(
+            sink.write(&[enrich_to_rows(sample_search()), enrich_to_rows(search)]),
+            Err(SqliteSinkError::MissingSearchId)
+        ));
+        let count: i64 = sink
+            .connection()
+            .query_row("SELECT COUNT(*) FROM db_searches", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,333 @@
+use std::f64::consts::PI;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Compression of `TDigest::default()`: at most about 100 centroids, quantiles within 1% or so.
+pub const DEFAULT_COMPRESSION: f64 = 100.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Centroid {
+    pub mean: f64,
+    pub weight: f64,
+}
+
+/// A merging t-digest (Dunning, 2019): a memory-bounded summary of a distribution, that estimates
+/// its quantiles, most accurately near the tails. Digests built separately (on partitions, time
+/// windows...) merge into the digest of all their values.
+///
+/// `compression` bounds the number of centroids kept. Count, minimum, maximum and mean are exact.
+#[derive(Clone, Debug)]
+pub struct TDigest {
+    compression: f64,
+    centroids: Vec<Centroid>,
+    /// Values not merged into the centroids yet.
+    buffer: Vec<Centroid>,
+    count: f64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for TDigest {
+    fn default() -> Self {
+        Self::new(DEFAULT_COMPRESSION)
+    }
+}
+
+/// Scale function k1: centroids near the tails (q close to 0 or 1) hold fewer values.
+fn k(q: f64, compression: f64) -> f64 {
+    compression / (2.0 * PI) * (2.0 * q - 1.0).asin()
+}
+
+fn q(k: f64, compression: f64) -> f64 {
+    ((k * 2.0 * PI / compression).min(PI / 2.0).sin() + 1.0) / 2.0
+}
+
+impl TDigest {
+    pub fn new(compression: f64) -> Self {
+        TDigest {
+            compression,
+            centroids: vec![],
+            buffer: vec![],
+            count: 0.0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.add_centroid(Centroid {
+            mean: value,
+            weight: 1.0,
+        });
+    }
+
+    fn add_centroid(&mut self, centroid: Centroid) {
+        self.buffer.push(centroid);
+        self.count += centroid.weight;
+        self.sum += centroid.mean * centroid.weight;
+        self.min = self.min.min(centroid.mean);
+        self.max = self.max.max(centroid.mean);
+        if self.buffer.len() as f64 > 5.0 * self.compression {
+            self.compress();
+        }
+    }
+
+    /// Adds all the values of another digest.
+    pub fn merge(&mut self, other: &TDigest) {
+        for centroid in other.centroids.iter().chain(&other.buffer) {
+            self.add_centroid(*centroid);
+        }
+        // keeps the exact extremes of the other digest, that its centroids may have averaged
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    /// Merges the buffered values into the centroids.
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut all = std::mem::take(&mut self.centroids);
+        all.append(&mut self.buffer);
+        all.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+
+        let mut centroids = Vec::with_capacity(all.len());
+        let mut weight_before = 0.0;
+        let mut weight_limit = self.count * q(k(0.0, self.compression) + 1.0, self.compression);
+        let mut current = all[0];
+        for centroid in &all[1..] {
+            if weight_before + current.weight + centroid.weight <= weight_limit {
+                let weight = current.weight + centroid.weight;
+                current.mean += (centroid.mean - current.mean) * centroid.weight / weight;
+                current.weight = weight;
+            } else {
+                weight_before += current.weight;
+                weight_limit = self.count
+                    * q(
+                        k(weight_before / self.count, self.compression) + 1.0,
+                        self.compression,
+                    );
+                centroids.push(current);
+                current = *centroid;
+            }
+        }
+        centroids.push(current);
+        self.centroids = centroids;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count as u64
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        (self.count > 0.0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        (self.count > 0.0).then_some(self.max)
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0.0).then(|| self.sum / self.count)
+    }
+
+    /// Estimated value at quantile `q`, between 0 and 1: values are interpolated between the
+    /// centers of the centroids, and between the extremes and the first and last centroids.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0.0 {
+            return None;
+        }
+        let mut digest = self.clone();
+        digest.compress();
+        let centroids = &digest.centroids;
+
+        let target = q.clamp(0.0, 1.0) * self.count;
+        let first = centroids[0];
+        if target < first.weight / 2.0 {
+            return Some(self.min + (first.mean - self.min) * target / (first.weight / 2.0));
+        }
+        let last = centroids[centroids.len() - 1];
+        if target > self.count - last.weight / 2.0 {
+            let from_end = self.count - target;
+            return Some(self.max - (self.max - last.mean) * from_end / (last.weight / 2.0));
+        }
+
+        let mut center = first.weight / 2.0;
+        for pair in centroids.windows(2) {
+            let next_center = center + (pair[0].weight + pair[1].weight) / 2.0;
+            if target <= next_center {
+                let ratio = (target - center) / (next_center - center);
+                return Some(pair[0].mean + (pair[1].mean - pair[0].mean) * ratio);
+            }
+            center = next_center;
+        }
+        Some(last.mean)
+    }
+}
+
+/// Serialized form of a digest, with its buffer merged.
+#[derive(Serialize, Deserialize)]
+struct SerializedTDigest {
+    compression: f64,
+    count: f64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    centroids: Vec<Centroid>,
+}
+
+impl SerializedTDigest {
+    /// Checks that the digest is one `TDigest` could have serialized, as quantiles of an
+    /// inconsistent one are meaningless.
+    fn validate(&self) -> Result<(), &'static str> {
+        if !(self.compression.is_finite() && self.compression > 0.0) {
+            return Err("compression must be a positive number");
+        }
+        let values = [self.count, self.sum, self.min, self.max];
+        let centroids = self
+            .centroids
+            .iter()
+            .flat_map(|centroid| [centroid.mean, centroid.weight]);
+        if !values.into_iter().chain(centroids).all(f64::is_finite) {
+            return Err("values must be finite");
+        }
+        if self.count < 0.0 || self.centroids.iter().any(|centroid| centroid.weight <= 0.0) {
+            return Err("count and weights must be positive");
+        }
+        if self.count > 0.0 && (self.centroids.is_empty() || self.min > self.max) {
+            return Err("a digest with values must have centroids between min and max");
+        }
+        let weights: f64 = self.centroids.iter().map(|centroid| centroid.weight).sum();
+        if (weights - self.count).abs() > 1e-9 * self.count.max(1.0) {
+            return Err("the weights of the centroids must add up to count");
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for TDigest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut digest = self.clone();
+        digest.compress();
+        SerializedTDigest {
+            compression: digest.compression,
+            count: digest.count,
+            sum: digest.sum,
+            // infinite when empty, which JSON cannot hold
+            min: digest.min().unwrap_or(0.0),
+            max: digest.max().unwrap_or(0.0),
+            centroids: digest.centroids,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TDigest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let serialized = SerializedTDigest::deserialize(deserializer)?;
+        serialized.validate().map_err(de::Error::custom)?;
+        let mut digest = TDigest::new(serialized.compression);
+        if serialized.count > 0.0 {
+            digest.centroids = serialized.centroids;
+            digest.count = serialized.count;
+            digest.sum = serialized.sum;
+            digest.min = serialized.min;
+            digest.max = serialized.max;
+        }
+        Ok(digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantiles_are_close_and_memory_bounded() {
+        let mut digest = TDigest::default();
+        // 1..=100000, shuffled deterministically
+        for i in 0..100_000u64 {
+            digest.add((i * 7919 % 100_000 + 1) as f64);
+        }
+
+        assert_eq!(digest.count(), 100_000);
+        assert_eq!(digest.min(), Some(1.0));
+        assert_eq!(digest.max(), Some(100_000.0));
+        assert_eq!(digest.mean(), Some(50_000.5));
+        for q in [0.01, 0.1, 0.5, 0.9, 0.99] {
+            let estimate = digest.quantile(q).unwrap();
+            assert!((estimate - q * 100_000.0).abs() < 500.0, "{q}: {estimate}");
+        }
+        digest.compress();
+        assert!(digest.centroids.len() <= 2 * DEFAULT_COMPRESSION as usize);
+    }
+
+    #[test]
+    fn test_few_values_are_exact() {
+        let mut digest = TDigest::default();
+        assert_eq!(digest.quantile(0.5), None);
+        for value in [100.0, 199.99, 150.0] {
+            digest.add(value);
+        }
+        assert_eq!(digest.quantile(0.0), Some(100.0));
+        assert_eq!(digest.quantile(0.5), Some(150.0));
+        assert_eq!(digest.quantile(1.0), Some(199.99));
+    }
+
+    #[test]
+    fn test_merge_and_serialization() {
+        let mut low = TDigest::default();
+        let mut high = TDigest::default();
+        for i in 1..=1000 {
+            low.add(i as f64);
+            high.add((i + 1000) as f64);
+        }
+
+        let json = serde_json::to_string(&high).unwrap();
+        let high: TDigest = serde_json::from_str(&json).unwrap();
+        low.merge(&high);
+
+        assert_eq!(low.count(), 2000);
+        assert_eq!(low.min(), Some(1.0));
+        assert_eq!(low.max(), Some(2000.0));
+        assert!((low.quantile(0.5).unwrap() - 1000.0).abs() < 20.0);
+
+        let empty: TDigest =
+            serde_json::from_str(&serde_json::to_string(&TDigest::default()).unwrap()).unwrap();
+        assert_eq!(empty.count(), 0);
+        assert_eq!(empty.min(), None);
+    }
+
+    #[test]
+    fn test_invalid_serialization() {
+        let valid = r#"{"compression": 100.0, "count": 3.0, "sum": 6.0, "min": 1.0, "max": 3.0, "centroids": [{"mean": 1.0, "weight": 1.0}, {"mean": 2.5, "weight": 2.0}]}"#;
+        assert_eq!(serde_json::from_str::<TDigest>(valid).unwrap().count(), 3);
+
+        for (from, to) in [
+            (
+                r#""centroids": [{"mean": 1.0, "weight": 1.0}, {"mean": 2.5, "weight": 2.0}]"#,
+                r#""centroids": []"#,
+            ),
+            (r#""weight": 2.0"#, r#""weight": 3.0"#),
+            (r#""weight": 2.0"#, r#""weight": -2.0"#),
+            (r#""compression": 100.0"#, r#""compression": 0.0"#),
+            (r#""compression": 100.0"#, r#""compression": -1.0"#),
+            (r#""min": 1.0"#, r#""min": 4.0"#),
+        ] {
+            let invalid = valid.replace(from, to);
+            assert!(
+                serde_json::from_str::<TDigest>(&invalid).is_err(),
+                "{invalid}"
+            );
+        }
+
+        let mut digest = serde_json::from_str::<SerializedTDigest>(valid).unwrap();
+        digest.centroids[0].mean = f64::NAN;
+        assert!(digest.validate().is_err());
+        digest.centroids[0].mean = 1.0;
+        digest.sum = f64::INFINITY;
+        assert!(digest.validate().is_err());
+    }
+}